@@ -1,12 +1,14 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{self, pubkey};
-use anchor_spl::token::{self, Token, TokenAccount, Transfer, Mint};
+use anchor_spl::token::{self, Transfer};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use std::collections::HashMap;
 use anchor_lang::solana_program::system_program;
-use std::str::FromStr;
+use governance::{ConfigRegistry, GovernanceState};
 declare_id!("EkDU4dizCrRyaNfRfTcsHFH4rTmeBP4PQBkH74Ua3RvD");
 
-mod transfer_helper;
+pub mod transfer_helper;
+mod masscall_helper;
 
 #[program]
 pub mod dextra {
@@ -69,6 +71,11 @@ pub mod dextra {
         did: u64
     ) -> Result<(u64, i64, i64, bool)> {
         require!(pid < ctx.accounts.protocol.pool_count, ErrorCode::PoolDoesNotExist);
+        let (pool, _) = Pubkey::find_program_address(
+            &[b"pool", ctx.accounts.protocol.key().as_ref(), &pid.to_le_bytes()],
+            ctx.program_id,
+        );
+        require_keys_eq!(ctx.accounts.user_info.pool, pool, ErrorCode::InvalidUserInfoPool);
         
         // Ensure the deposit index is valid
         require!(did < ctx.accounts.user_info.deposits.len() as u64, ErrorCode::InvalidAmount);
@@ -99,8 +106,11 @@ pub mod dextra {
 
         protocol.owner = ctx.accounts.owner.key();
         protocol.governance = ctx.accounts.owner.key();
-        protocol.ref_percent = 200; // 2%
+        protocol.ref_percent = REF_PERCENT;
         protocol.pool_count = 0;
+        protocol.config_registry = Pubkey::default();
+        protocol.referral_min_stake = 0;
+        protocol.referral_min_duration = 0;
         protocol.referrers = Vec::new();
         protocol.claimable_users = Vec::new();
         protocol.withdrawable_users = Vec::new();
@@ -113,11 +123,25 @@ pub mod dextra {
         user_info.referrer = Pubkey::default();
         user_info.total_claimed = 0;
         user_info.deposits = Vec::new();
+        user_info.pending_stream_rewards = Vec::new();
+        user_info.reward_debt = 0;
+        user_info.next_unstake_id = 0;
+        user_info.unstake_requests = Vec::new();
+        user_info.pool = Pubkey::default();
+
+        ctx.accounts.protocol_stats.bump = ctx.bumps.protocol_stats;
+
+        Ok(())
+    }
 
+    // Creates the stats account for protocols initialized before it existed
+    pub fn initialize_protocol_stats(ctx: Context<InitializeProtocolStats>) -> Result<()> {
+        ctx.accounts.protocol_stats.bump = ctx.bumps.protocol_stats;
         Ok(())
     }
 
-    // Add a new pool
+    // Add a new pool, a missing deposit or reward mint makes that side native SOL.
+    // Shared-emissions pools start without emissions, see `set_emission_rate`.
     pub fn add_pool(
         ctx: Context<AddPool>,
         minimum_deposit: u64,
@@ -125,14 +149,29 @@ pub mod dextra {
         can_swap: bool,
         rate: u64,
         apy: u64,
+        accounting_mode: AccountingMode,
     ) -> Result<()> {
+        check_config_limit(&ctx.accounts.protocol, &ctx.accounts.config_registry, governance::MAX_APY_KEY, apy)?;
+
         let protocol = &mut ctx.accounts.protocol;
         let pool = &mut ctx.accounts.pool;
         let timestamp = Clock::get()?.unix_timestamp;
         let start_date = date_helper::get_start_of_date(timestamp);
 
-        pool.deposit_token = ctx.accounts.deposit_token.key();
-        pool.reward_token = ctx.accounts.reward_token.key();
+        for mint in [&ctx.accounts.deposit_token, &ctx.accounts.reward_token].into_iter().flatten() {
+            transfer_helper::ensure_supported_mint(&mint.to_account_info())?;
+        }
+
+        pool.deposit_token = ctx.accounts.deposit_token.as_ref().map(|mint| mint.key()).unwrap_or_default();
+        pool.reward_token = ctx.accounts.reward_token.as_ref().map(|mint| mint.key()).unwrap_or_default();
+        require!(!can_swap || !pool.has_native_side(), ErrorCode::SwapNotSupported);
+
+        if pool.has_native_side() {
+            let sol_vault = ctx.accounts.sol_vault.as_mut().ok_or(ErrorCode::SolVaultRequired)?;
+            sol_vault.pool = pool.key();
+            sol_vault.bump = ctx.bumps.sol_vault.ok_or(ErrorCode::SolVaultRequired)?;
+        }
+
         pool.minimum_deposit = minimum_deposit;
         pool.lock_period = lock_period;
         pool.can_swap = can_swap;
@@ -140,6 +179,9 @@ pub mod dextra {
         pool.last_apy = apy;
         pool.set_rate(start_date, rate);
         pool.set_apy(start_date, apy);
+        pool.accounting_mode = accounting_mode;
+        pool.last_reward_timestamp = timestamp;
+        pool.start_ts = timestamp;
 
         protocol.pool_count += 1;
         Ok(())
@@ -152,47 +194,146 @@ pub mod dextra {
         amount: u64,
         referrer: Option<Pubkey>,
     ) -> Result<()>  {
-        let pool = &ctx.accounts.pool;
+        let pool = &mut ctx.accounts.pool;
         let user_info = &mut ctx.accounts.user_info;
         let protocol = &mut ctx.accounts.protocol;
         let clock = Clock::get()?;
+        if let Some(history) = ctx.accounts.pool_history.as_mut() {
+            history.record(pool, clock.unix_timestamp);
+        }
 
         require!(amount >= pool.minimum_deposit, ErrorCode::InsufficientDeposit);
+        require!(pool.is_open(clock.unix_timestamp), ErrorCode::PoolNotOpen);
+        // The per-user cap counts the wallet, a fresh user info doesn't reset it
+        pool.deposit_caps.check(amount, ctx.accounts.wallet_position.amount, pool.total_deposited)?;
+
+        // Transfer tokens, native SOL goes into the pool's vault. Mints with a transfer fee
+        // deliver less than `amount`, only what actually arrived is credited.
+        let amount = if pool.is_native_deposit() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_in(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                &sol_vault.to_account_info(),
+                amount,
+            )?;
+            amount
+        } else {
+            let deposit_mint = ctx.accounts.deposit_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let protocol_token_account = ctx.accounts.protocol_token_account.as_mut().ok_or(ErrorCode::TokenAccountRequired)?;
+            let balance_before = protocol_token_account.amount;
+            transfer_helper::transfer_tokens_checked(
+                &ctx.accounts.token_program.to_account_info(),
+                &user_token_account.to_account_info(),
+                deposit_mint,
+                &protocol_token_account.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                amount,
+            )?;
+            protocol_token_account.reload()?;
+            protocol_token_account.amount
+                .checked_sub(balance_before)
+                .ok_or(ErrorCode::ArithmeticError)?
+        };
 
-        // Set the authority field when initializing the account
+        // Set the authority field when initializing the account, a position can't change hands
+        require!(
+            user_info.authority == Pubkey::default() || user_info.authority == ctx.accounts.user.key(),
+            ErrorCode::InvalidAuthority
+        );
         user_info.authority = ctx.accounts.user.key();
 
+        // Wallets count as depositors once per pool and once protocol-wide, however many
+        // user infos they open
+        let stats = &mut ctx.accounts.protocol_stats;
+        let wallet_position = &mut ctx.accounts.wallet_position;
+        if wallet_position.user == Pubkey::default() {
+            pool.stats.depositors = pool.stats.depositors.saturating_add(1);
+        }
+        let depositor = &mut ctx.accounts.depositor;
+        if depositor.user == Pubkey::default() {
+            depositor.user = ctx.accounts.user.key();
+            depositor.bump = ctx.bumps.depositor;
+            stats.depositors = stats.depositors.saturating_add(1);
+        }
+        wallet_position.pool = pool.key();
+        wallet_position.user = ctx.accounts.user.key();
+        wallet_position.bump = ctx.bumps.wallet_position;
+        wallet_position.amount = wallet_position.amount.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+
+        // A position belongs to the pool it was opened in
+        if user_info.pool == Pubkey::default() {
+            user_info.pool = pool.key();
+        }
+        require_keys_eq!(user_info.pool, pool.key(), ErrorCode::InvalidUserInfoPool);
+
+        // A referral code stands in for the referrer's pubkey
+        let referrer = match ctx.accounts.referral_code.as_ref() {
+            Some(referral_code) => {
+                require!(
+                    referrer.is_none() || referrer == Some(referral_code.referrer),
+                    ErrorCode::InvalidReferralCode
+                );
+                Some(referral_code.referrer)
+            }
+            None => referrer,
+        };
+
         // Setup referrer if provided
         if let Some(ref_address) = referrer {
             protocol.setup_referrer(ctx.accounts.user.key(), ref_address)?;
         }
 
+        // Stake brought in counts towards the direct referrer's stats and tier, the
+        // wallet's first referred deposit in this pool makes it one of the pool's referees
+        if let Some(user_referrer) = protocol.get_referrer(&ctx.accounts.user.key()) {
+            user_info.referrer = user_referrer;
+            let referrer_account = ctx.accounts.referrer_account.as_mut().ok_or(ErrorCode::ReferrerAccountRequired)?;
+            require_keys_eq!(referrer_account.referrer, user_referrer, ErrorCode::InvalidReferrerAccount);
+            let new_referee = !wallet_position.referee_counted;
+            wallet_position.referee_counted = true;
+            referrer_account.record_referral(amount, new_referee)?;
+        }
+
         // Calculate pending reward
-        let pending_reward = calculate_reward(pool_id, user_info, pool)?;
-        user_info.pending_reward = pending_reward;
+        settle_pending_reward(pool_id, user_info, pool)?;
+        update_stream_rewards(user_info, pool)?;
+        pool.update_emissions(clock.unix_timestamp)?;
+        pool.accrue_liabilities(clock.unix_timestamp)?;
+
+        // A funded pool only takes deposits it can keep paying for
+        if pool.budget_enforced {
+            let total_after = pool.total_deposited.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+            let forward = pool.estimate_rewards(total_after, pool.remaining_term(clock.unix_timestamp))?;
+            // Every reward may still pay out a full referral chain on top
+            let forward = forward
+                .checked_mul(10000 + MAX_TOTAL_REF_PERCENT)
+                .map(|value| value / 10000)
+                .ok_or(ErrorCode::ArithmeticError)?;
+            let required = pool.reward_liabilities.checked_add(forward).ok_or(ErrorCode::ArithmeticError)?;
+            require!(required <= pool.reward_budget, ErrorCode::PoolInsolvent);
+        }
+
+        // A user with nothing staked opens a position
+        if user_info.amount == 0 {
+            pool.stats.active_positions = pool.stats.active_positions.saturating_add(1);
+            stats.active_positions = stats.active_positions.saturating_add(1);
+        }
 
         // Update user info
         user_info.amount = match user_info.amount.checked_add(amount) {
             Some(result) => result,
             None => return err!(ErrorCode::ArithmeticError)
         };
+        pool.total_deposited = pool.total_deposited.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_info.reward_debt = pool.reward_debt_for(user_info.amount)?;
         user_info.last_claimed = clock.unix_timestamp as u64;
         
         if user_info.stake_timestamp == 0 {
             user_info.stake_timestamp = clock.unix_timestamp;
         }
 
-        // Transfer tokens
-        let transfer_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.user_token_account.to_account_info(),
-                to: ctx.accounts.protocol_token_account.to_account_info(),
-                authority: ctx.accounts.user.to_account_info(),
-            },
-        );
-        token::transfer(transfer_ctx, amount)?;
-
         // Add deposit record
         user_info.deposits.push(UserDeposit {
             amount,
@@ -212,9 +353,16 @@ pub mod dextra {
     }
 
     // Implement claim function
-    pub fn claim<'info>(ctx: Context<'_, '_, '_, 'info, Claim<'info>>, pool_id: u64) -> Result<()> {
+    // Pays the base reward and every reward stream. Stream accounts are passed as
+    // (mint, vault, user token account, mint's token program) in the remaining accounts,
+    // one group per stream in stream order, ahead of any referrer accounts.
+    pub fn claim<'info>(ctx: Context<'_, '_, 'info, 'info, Claim<'info>>, pool_id: u64) -> Result<()> {
         let reward = ctx.accounts.user_info.pending_reward;
-        require!(reward > 0, ErrorCode::NoReward);
+        let stream_rewards = ctx.accounts.user_info.pending_stream_rewards.clone();
+        require!(reward > 0 || stream_rewards.iter().any(|amount| *amount > 0), ErrorCode::NoReward);
+        if let Some(history) = ctx.accounts.pool_history.as_mut() {
+            history.record(&ctx.accounts.pool, Clock::get()?.unix_timestamp);
+        }
         require!(!ctx.accounts.protocol.is_claimable(&ctx.accounts.user.key()), ErrorCode::Unauthorized);
 
         // Get bump from account info
@@ -223,39 +371,80 @@ pub mod dextra {
         let signer = &[&seeds[..]];
 
         // Transfer reward tokens to the user (not the referrer)
-        let transfer_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.protocol_vault.to_account_info(),
-                to: ctx.accounts.user_token_account.to_account_info(),
-                authority: ctx.accounts.protocol.to_account_info(),
-            },
-            signer,
-        );
-        token::transfer(transfer_ctx, reward)?;
+        if reward == 0 {
+            msg!("No base reward pending");
+        } else if ctx.accounts.pool.is_native_reward() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_out(
+                &sol_vault.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                reward,
+            )?;
+        } else {
+            let reward_mint = ctx.accounts.reward_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let protocol_vault = ctx.accounts.protocol_vault.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            transfer_helper::transfer_tokens_checked_signed(
+                &ctx.accounts.token_program.to_account_info(),
+                &protocol_vault.to_account_info(),
+                reward_mint,
+                &user_token_account.to_account_info(),
+                &ctx.accounts.protocol.to_account_info(),
+                signer,
+                reward,
+            )?;
+        }
 
-        // Process referral reward if applicable
-        if ctx.accounts.user_info.referrer != Pubkey::default() {
-            let ref_amount = match reward.checked_mul(ctx.accounts.protocol.ref_percent) {
-                Some(val) => match val.checked_div(10000) {
-                    Some(result) => result,
-                    None => return err!(ErrorCode::ArithmeticError)
-                },
-                None => return err!(ErrorCode::ArithmeticError)
-            };
+        // Accrue referral rewards to every referrer up the user's chain
+        let referral_accrued = if reward > 0 {
+            process_ref_reward(&ctx, pool_id, reward)?
+        } else {
+            0
+        };
+        ctx.accounts.pool.record_reward_payout(reward)?;
+        ctx.accounts.pool.record_referral_accrual(referral_accrued)?;
+        ctx.accounts.pool.stats.rewards_paid = ctx.accounts.pool.stats.rewards_paid.saturating_add(reward);
+
+        let mut rewards = vec![RewardAmount { mint: ctx.accounts.pool.reward_token, amount: reward }];
+        for (index, stream) in ctx.accounts.pool.reward_streams.iter_mut().enumerate() {
+            let amount = stream_rewards.get(index).copied().unwrap_or(0);
+            rewards.push(RewardAmount { mint: stream.mint, amount });
+            if amount == 0 {
+                continue;
+            }
 
-            process_ref_reward(&ctx, pool_id, ref_amount, ctx.accounts.user_info.referrer, protocol_bump)?;
+            let accounts = ctx.remaining_accounts
+                .get(index * 4..index * 4 + 4)
+                .ok_or(ErrorCode::RewardStreamAccountsRequired)?;
+            require_keys_eq!(accounts[0].key(), stream.mint, ErrorCode::InvalidMint);
+            require_keys_eq!(accounts[1].key(), stream.vault, ErrorCode::InvalidRewardVault);
+            // A stream mint may live under another token program than the pool's
+            require_keys_eq!(accounts[3].key(), *accounts[0].owner, ErrorCode::InvalidProgramId);
+            let mint = InterfaceAccount::<Mint>::try_from(&accounts[0])?;
+
+            transfer_helper::transfer_tokens_checked_signed(
+                &accounts[3],
+                &accounts[1],
+                &mint,
+                &accounts[2],
+                &ctx.accounts.protocol.to_account_info(),
+                signer,
+                amount,
+            )?;
+            stream.total_paid = stream.total_paid.saturating_add(amount);
         }
 
         // After successful claim, update pending reward and total claimed
         ctx.accounts.user_info.total_claimed = ctx.accounts.user_info.total_claimed.checked_add(reward)
             .ok_or(ErrorCode::ArithmeticError)?;
         ctx.accounts.user_info.pending_reward = 0;
+        ctx.accounts.user_info.pending_stream_rewards.iter_mut().for_each(|amount| *amount = 0);
         
         emit!(ClaimEvent {
             user: ctx.accounts.user.key(),
             pool_id,
             amount: reward,
+            rewards,
         });
 
         Ok(())
@@ -266,29 +455,48 @@ pub mod dextra {
         // First, check if pool exists
         require!(pool_id < ctx.accounts.protocol.pool_count, ErrorCode::PoolDoesNotExist);
         
-        let pool = &ctx.accounts.pool;
+        let pool = &mut ctx.accounts.pool;
         let user_info = &mut ctx.accounts.user_info;
+        if let Some(history) = ctx.accounts.pool_history.as_mut() {
+            history.record(pool, Clock::get()?.unix_timestamp);
+        }
         
+        // Pools with a cooldown go through request_unstake / complete_unstake
+        require!(pool.cooldown == 0, ErrorCode::UnbondingRequired);
+
         let available_amount = calculate_sum_available_for_withdraw(user_info)?;
         require!(available_amount > 0, ErrorCode::NothingToWithdraw);
         require!(user_info.amount >= available_amount, ErrorCode::InsufficientAmount);
         require!(!ctx.accounts.protocol.is_withdrawable(&ctx.accounts.user.key()), ErrorCode::Unauthorized);
+        release_referred_stake(user_info, ctx.accounts.referrer_account.as_mut(), available_amount)?;
+        if let Some(wallet_position) = ctx.accounts.wallet_position.as_mut() {
+            wallet_position.release(available_amount);
+        }
         
         // First update pending reward (matching Solidity implementation)
-        let pending_reward = calculate_reward(pool_id, user_info, pool)?;
-        user_info.pending_reward = pending_reward;
+        settle_pending_reward(pool_id, user_info, pool)?;
+        update_stream_rewards(user_info, pool)?;
+        let timestamp = Clock::get()?.unix_timestamp;
+        pool.update_emissions(timestamp)?;
+        pool.accrue_liabilities(timestamp)?;
         
         // Update last claimed timestamp
-        user_info.last_claimed = Clock::get()?.unix_timestamp as u64;
+        user_info.last_claimed = timestamp as u64;
         
         // Update amount with proper error handling
         user_info.amount = match user_info.amount.checked_sub(available_amount) {
             Some(result) => result,
             None => return err!(ErrorCode::ArithmeticError)
         };
+        // Positions from before the pool tracked its total are not part of it
+        pool.total_deposited = pool.total_deposited.saturating_sub(available_amount);
+        user_info.reward_debt = pool.reward_debt_for(user_info.amount)?;
 
         // Reset timestamps if amount is 0
         if user_info.amount == 0 {
+            let stats = &mut ctx.accounts.protocol_stats;
+            pool.stats.active_positions = pool.stats.active_positions.saturating_sub(1);
+            stats.active_positions = stats.active_positions.saturating_sub(1);
             user_info.stake_timestamp = 0;
             user_info.last_claimed = 0;
             // No need to set stake_timestamp again (Solidity has a duplicate line)
@@ -298,19 +506,30 @@ pub mod dextra {
         mark_deposits_as_withdrawn(user_info)?;
         
         // Transfer deposit tokens back to user (after updating state)
-        let seeds = &[b"protocol" as &[u8], &[ctx.bumps.protocol]];
-        let signer = &[&seeds[..]];
-        
-        let transfer_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.protocol_token_account.to_account_info(),
-                to: ctx.accounts.user_token_account.to_account_info(),
-                authority: ctx.accounts.protocol.to_account_info(),
-            },
-            signer,
-        );
-        token::transfer(transfer_ctx, available_amount)?;
+        if pool.is_native_deposit() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_out(
+                &sol_vault.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                available_amount,
+            )?;
+        } else {
+            let seeds = &[b"protocol" as &[u8], &[ctx.bumps.protocol]];
+            let signer = &[&seeds[..]];
+            let deposit_mint = ctx.accounts.deposit_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let protocol_token_account = ctx.accounts.protocol_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+
+            transfer_helper::transfer_tokens_checked_signed(
+                &ctx.accounts.token_program.to_account_info(),
+                &protocol_token_account.to_account_info(),
+                deposit_mint,
+                &user_token_account.to_account_info(),
+                &ctx.accounts.protocol.to_account_info(),
+                signer,
+                available_amount,
+            )?;
+        }
 
         emit!(WithdrawEvent {
             user: ctx.accounts.user.key(),
@@ -321,34 +540,165 @@ pub mod dextra {
         Ok(())
     }
 
+    // Starts unbonding `amount` of the user's unlocked deposits. It stops earning right away
+    // and can be taken out with `complete_unstake` once the pool's cooldown has passed.
+    pub fn request_unstake(ctx: Context<RequestUnstake>, pool_id: u64, amount: u64) -> Result<()> {
+        require!(pool_id < ctx.accounts.protocol.pool_count, ErrorCode::PoolDoesNotExist);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!ctx.accounts.protocol.is_withdrawable(&ctx.accounts.user.key()), ErrorCode::Unauthorized);
+
+        let pool = &mut ctx.accounts.pool;
+        let user_info = &mut ctx.accounts.user_info;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        require!(calculate_sum_available_for_withdraw(user_info)? >= amount, ErrorCode::InsufficientAmount);
+        require!(user_info.amount >= amount, ErrorCode::InsufficientAmount);
+        require!(user_info.unstake_requests.len() < UserInfo::MAX_UNSTAKE_REQUESTS, ErrorCode::UnstakeRequestLimit);
+        release_referred_stake(user_info, ctx.accounts.referrer_account.as_mut(), amount)?;
+        if let Some(wallet_position) = ctx.accounts.wallet_position.as_mut() {
+            wallet_position.release(amount);
+        }
+
+        // Settle rewards up to now, the unbonding amount earns nothing from here on
+        settle_pending_reward(pool_id, user_info, pool)?;
+        update_stream_rewards(user_info, pool)?;
+        pool.update_emissions(timestamp)?;
+        pool.accrue_liabilities(timestamp)?;
+        user_info.last_claimed = timestamp as u64;
+
+        user_info.amount = user_info.amount.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        pool.total_deposited = pool.total_deposited.saturating_sub(amount);
+        pool.total_unbonding = pool.total_unbonding.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_info.reward_debt = pool.reward_debt_for(user_info.amount)?;
+        take_unlocked_deposits(user_info, amount, timestamp)?;
+
+        if user_info.amount == 0 {
+            let stats = &mut ctx.accounts.protocol_stats;
+            pool.stats.active_positions = pool.stats.active_positions.saturating_sub(1);
+            stats.active_positions = stats.active_positions.saturating_sub(1);
+            user_info.stake_timestamp = 0;
+            user_info.last_claimed = 0;
+        }
+
+        let request_id = user_info.next_unstake_id;
+        let available_at = timestamp.checked_add(pool.cooldown).ok_or(ErrorCode::ArithmeticError)?;
+        user_info.next_unstake_id = request_id.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+        user_info.unstake_requests.push(UnstakeRequest {
+            id: request_id,
+            pool: pool.key(),
+            amount,
+            available_at,
+        });
+
+        emit!(UnstakeRequestedEvent {
+            user: ctx.accounts.user.key(),
+            pool_id,
+            request_id,
+            amount,
+            available_at,
+        });
+
+        Ok(())
+    }
+
+    // Pays out an unstake request whose cooldown has passed
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>, pool_id: u64, request_id: u64) -> Result<()> {
+        require!(pool_id < ctx.accounts.protocol.pool_count, ErrorCode::PoolDoesNotExist);
+
+        let pool = &mut ctx.accounts.pool;
+        let user_info = &mut ctx.accounts.user_info;
+
+        let index = user_info.unstake_requests
+            .iter()
+            .position(|request| request.id == request_id && request.pool == pool.key())
+            .ok_or(ErrorCode::UnstakeRequestNotFound)?;
+        require!(
+            Clock::get()?.unix_timestamp >= user_info.unstake_requests[index].available_at,
+            ErrorCode::CooldownNotElapsed
+        );
+        let amount = user_info.unstake_requests.remove(index).amount;
+        pool.total_unbonding = pool.total_unbonding.saturating_sub(amount);
+
+        if pool.is_native_deposit() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_out(
+                &sol_vault.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                amount,
+            )?;
+        } else {
+            let seeds = &[b"protocol" as &[u8], &[ctx.bumps.protocol]];
+            let signer = &[&seeds[..]];
+            let deposit_mint = ctx.accounts.deposit_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let protocol_token_account = ctx.accounts.protocol_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+
+            transfer_helper::transfer_tokens_checked_signed(
+                &ctx.accounts.token_program.to_account_info(),
+                &protocol_token_account.to_account_info(),
+                deposit_mint,
+                &user_token_account.to_account_info(),
+                &ctx.accounts.protocol.to_account_info(),
+                signer,
+                amount,
+            )?;
+        }
+
+        emit!(WithdrawEvent {
+            user: ctx.accounts.user.key(),
+            pool_id,
+            amount,
+        });
+
+        Ok(())
+    }
+
     // Implement swap function
     pub fn swap(ctx: Context<Swap>, pool_id: u64, amount: u64, direction: bool) -> Result<()> {
-        let pool = &ctx.accounts.pool;
+        let pool = &mut ctx.accounts.pool;
         require!(pool.can_swap, ErrorCode::SwapNotSupported);
+        if let Some(history) = ctx.accounts.pool_history.as_mut() {
+            history.record(pool, Clock::get()?.unix_timestamp);
+        }
+
+        // Only the pool's own pair, deposit to reward when `direction` is set
+        let (input_token, output_token) = if direction {
+            (pool.deposit_token, pool.reward_token)
+        } else {
+            (pool.reward_token, pool.deposit_token)
+        };
+        require_keys_eq!(ctx.accounts.input_mint.key(), input_token, ErrorCode::InvalidMint);
+        require_keys_eq!(ctx.accounts.output_mint.key(), output_token, ErrorCode::InvalidMint);
 
         let received_amount = calculate_swap(pool, amount, direction)?;
+        if direction {
+            pool.stats.swap_volume_to_reward = pool.stats.swap_volume_to_reward.saturating_add(amount);
+        } else {
+            pool.stats.swap_volume_to_deposit = pool.stats.swap_volume_to_deposit.saturating_add(amount);
+        }
 
         // Transfer input tokens to protocol
-        let transfer_in_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.user_input_account.to_account_info(),
-                to: ctx.accounts.protocol_input_account.to_account_info(),
-                authority: ctx.accounts.user.to_account_info(),
-            },
-        );
-        token::transfer(transfer_in_ctx, amount)?;
-
-        // Transfer output tokens to user
-        let transfer_out_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.protocol_output_account.to_account_info(),
-                to: ctx.accounts.user_output_account.to_account_info(),
-                authority: ctx.accounts.protocol.to_account_info(),
-            },
-        );
-        token::transfer(transfer_out_ctx, received_amount)?;
+        transfer_helper::transfer_tokens_checked(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.user_input_account.to_account_info(),
+            &ctx.accounts.input_mint,
+            &ctx.accounts.protocol_input_account.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            amount,
+        )?;
+
+        // Transfer output tokens to user, signed by the protocol PDA
+        let seeds = &[b"protocol" as &[u8], &[ctx.bumps.protocol]];
+        let signer = &[&seeds[..]];
+        transfer_helper::transfer_tokens_checked_signed(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.protocol_output_account.to_account_info(),
+            &ctx.accounts.output_mint,
+            &ctx.accounts.user_output_account.to_account_info(),
+            &ctx.accounts.protocol.to_account_info(),
+            signer,
+            received_amount,
+        )?;
 
         emit!(SwapEvent {
             user: ctx.accounts.user.key(),
@@ -372,6 +722,8 @@ pub mod dextra {
     }
 
     pub fn update_apy(ctx: Context<UpdatePool>, _pid: u64, new_apy: u64) -> Result<()> {
+        check_config_limit(&ctx.accounts.protocol, &ctx.accounts.config_registry, governance::MAX_APY_KEY, new_apy)?;
+
         let pool = &mut ctx.accounts.pool;
         let timestamp = Clock::get()?.unix_timestamp;
         let start_date = date_helper::get_start_of_date(timestamp);
@@ -386,14 +738,355 @@ pub mod dextra {
         minimum_deposit: u64,
         lock_period: i64,
         can_swap: bool,
+        deposit_caps: DepositCaps,
     ) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
+        require!(!can_swap || !pool.has_native_side(), ErrorCode::SwapNotSupported);
         pool.minimum_deposit = minimum_deposit;
         pool.lock_period = lock_period;
         pool.can_swap = can_swap;
+        pool.deposit_caps = deposit_caps;
         Ok(())
     }
     
+    // Pay an extra reward token on top of the base reward, from a protocol-owned vault. Streams
+    // are outside the reward budget, their vaults have to be kept funded separately.
+    pub fn add_reward_stream(ctx: Context<AddRewardStream>, _pid: u64, rate: u64, apy: u64) -> Result<()> {
+        check_config_limit(&ctx.accounts.protocol, &ctx.accounts.config_registry, governance::MAX_APY_KEY, apy)?;
+        transfer_helper::ensure_supported_mint(&ctx.accounts.reward_mint.to_account_info())?;
+
+        let pool = &mut ctx.accounts.pool;
+        let mint = ctx.accounts.reward_mint.key();
+        require!(pool.reward_streams.len() < Pool::MAX_REWARD_STREAMS, ErrorCode::RewardStreamLimit);
+        require!(
+            mint != pool.reward_token && pool.reward_streams.iter().all(|stream| stream.mint != mint),
+            ErrorCode::DuplicateRewardStream
+        );
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let start_date = date_helper::get_start_of_date(timestamp);
+        let mut stream = RewardStream {
+            mint,
+            vault: ctx.accounts.reward_vault.key(),
+            start_timestamp: timestamp,
+            ..Default::default()
+        };
+        stream.set_rate(start_date, rate);
+        stream.set_apy(start_date, apy);
+        pool.reward_streams.push(stream);
+        Ok(())
+    }
+
+    pub fn update_reward_stream(ctx: Context<UpdatePool>, _pid: u64, index: u8, rate: u64, apy: u64) -> Result<()> {
+        check_config_limit(&ctx.accounts.protocol, &ctx.accounts.config_registry, governance::MAX_APY_KEY, apy)?;
+
+        let stream = ctx.accounts.pool.reward_streams
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::InvalidRewardStream)?;
+        let start_date = date_helper::get_start_of_date(Clock::get()?.unix_timestamp);
+        stream.set_rate(start_date, rate);
+        stream.set_apy(start_date, apy);
+        Ok(())
+    }
+
+    // Adds reward tokens to the pool's budget. Once funded, the pool refuses deposits its
+    // budget can't cover over the remaining term. The budget covers the base reward and
+    // referral shares only, reward streams are paid from their own vaults unreserved.
+    pub fn fund_rewards(ctx: Context<FundRewards>, pool_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Only what arrives counts, reward mints may charge a transfer fee
+        let received = if ctx.accounts.pool.is_native_reward() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_in(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.authority.to_account_info(),
+                &sol_vault.to_account_info(),
+                amount,
+            )?;
+            amount
+        } else {
+            let reward_mint = ctx.accounts.reward_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let funder_token_account = ctx.accounts.funder_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let protocol_vault = ctx.accounts.protocol_vault.as_mut().ok_or(ErrorCode::TokenAccountRequired)?;
+            let balance_before = protocol_vault.amount;
+            transfer_helper::transfer_tokens_checked(
+                &ctx.accounts.token_program.to_account_info(),
+                &funder_token_account.to_account_info(),
+                reward_mint,
+                &protocol_vault.to_account_info(),
+                &ctx.accounts.authority.to_account_info(),
+                amount,
+            )?;
+            protocol_vault.reload()?;
+            protocol_vault.amount
+                .checked_sub(balance_before)
+                .ok_or(ErrorCode::ArithmeticError)?
+        };
+
+        let pool = &mut ctx.accounts.pool;
+        pool.reward_budget = pool.reward_budget.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+        pool.budget_enforced = true;
+
+        emit!(RewardsFundedEvent {
+            pool_id,
+            funder: ctx.accounts.authority.key(),
+            amount: received,
+            reward_budget: pool.reward_budget,
+        });
+        Ok(())
+    }
+
+    // Sets when the pool takes deposits. An `end_ts` of 0 keeps it open; past the end
+    // rewards stop accruing while withdrawals and claims carry on.
+    pub fn set_pool_schedule(ctx: Context<UpdatePool>, _pid: u64, start_ts: i64, end_ts: i64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(!pool.closed, ErrorCode::PoolClosed);
+        require!(end_ts == 0 || (end_ts > start_ts && end_ts >= timestamp), ErrorCode::InvalidPoolSchedule);
+
+        // Settle what accrued under the old schedule
+        pool.update_emissions(timestamp)?;
+        pool.accrue_liabilities(timestamp)?;
+        pool.start_ts = start_ts;
+        pool.end_ts = end_ts;
+        Ok(())
+    }
+
+    // Ends the pool for good. Rewards stop now, positions can still be withdrawn and claimed.
+    pub fn sunset_pool(ctx: Context<UpdatePool>, _pid: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(!pool.closed, ErrorCode::PoolClosed);
+
+        pool.update_emissions(timestamp)?;
+        pool.accrue_liabilities(timestamp)?;
+        if pool.end_ts == 0 || pool.end_ts > timestamp {
+            pool.end_ts = timestamp;
+        }
+        pool.closed = true;
+        Ok(())
+    }
+
+    // Reclaims the rent of a sunset pool once every position is withdrawn and every reward
+    // and referral share claimed, the SOL vault's balance goes to the authority.
+    pub fn close_pool(ctx: Context<ClosePool>, _pid: u64) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(pool.closed, ErrorCode::PoolNotClosed);
+        require!(pool.total_deposited == 0 && pool.total_unbonding == 0, ErrorCode::PoolNotEmpty);
+        require!(pool.rewards_owed == 0, ErrorCode::RewardsOutstanding);
+        require!(
+            !pool.has_native_side() || ctx.accounts.sol_vault.is_some(),
+            ErrorCode::SolVaultRequired
+        );
+        Ok(())
+    }
+
+    // Starts keeping daily history for a pool, anyone may pay for it
+    pub fn initialize_pool_history(ctx: Context<InitializePoolHistory>, _pid: u64) -> Result<()> {
+        let history = &mut ctx.accounts.pool_history;
+        history.pool = ctx.accounts.pool.key();
+        history.bump = ctx.bumps.pool_history;
+        history.record(&ctx.accounts.pool, Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // Seconds unstaked funds wait before they can be taken out, 0 keeps plain withdrawals
+    pub fn set_cooldown(ctx: Context<UpdatePool>, _pid: u64, cooldown: i64) -> Result<()> {
+        require!(cooldown >= 0, ErrorCode::InvalidCooldown);
+        ctx.accounts.pool.cooldown = cooldown;
+        Ok(())
+    }
+
+    // Changes the emission of a shared-emissions pool, what accrued so far is settled at the old rate
+    pub fn set_emission_rate(ctx: Context<UpdatePool>, _pid: u64, emission_per_second: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(pool.accounting_mode == AccountingMode::SharedEmissions, ErrorCode::InvalidAccountingMode);
+
+        pool.update_emissions(Clock::get()?.unix_timestamp)?;
+        pool.emission_per_second = emission_per_second;
+        Ok(())
+    }
+
+    pub fn set_ref_percent(ctx: Context<UpdateProtocolConfig>, new_ref_percent: u64) -> Result<()> {
+        check_config_limit(
+            &ctx.accounts.protocol,
+            &ctx.accounts.config_registry,
+            governance::MAX_REF_PERCENT_KEY,
+            new_ref_percent,
+        )?;
+
+        ctx.accounts.protocol.ref_percent = new_ref_percent;
+        Ok(())
+    }
+
+    // Per-pool referral levels in basis points, tiers raise every level for referrers with enough referred stake
+    pub fn set_referral_config(
+        ctx: Context<SetReferralConfig>,
+        level_percents: Vec<u64>,
+        tiers: Vec<ReferralTier>,
+    ) -> Result<()> {
+        let max_total = ReferralConfig::max_total_percent(&level_percents, &tiers)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        check_config_limit(
+            &ctx.accounts.protocol,
+            &ctx.accounts.config_registry,
+            governance::MAX_REF_PERCENT_KEY,
+            max_total,
+        )?;
+
+        ctx.accounts.pool.has_referral_config = true;
+        let referral_config = &mut ctx.accounts.referral_config;
+        referral_config.pool = ctx.accounts.pool.key();
+        referral_config.bump = ctx.bumps.referral_config;
+        referral_config.configure(level_percents, tiers)
+    }
+
+    // Opens a referrer's per-pool account, referral rewards accrue to it. Anyone can pay
+    // for it, claims up the chain need one for every linked referrer.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer_account = &mut ctx.accounts.referrer_account;
+        referrer_account.referrer = ctx.accounts.referrer.key();
+        referrer_account.pool = ctx.accounts.pool.key();
+        referrer_account.bump = ctx.bumps.referrer_account;
+        Ok(())
+    }
+
+    // Reserve a short shareable code that resolves to the signer as referrer
+    pub fn register_referral_code(ctx: Context<RegisterReferralCode>, code: String) -> Result<()> {
+        require!(ReferralCode::is_valid(&code), ErrorCode::InvalidReferralCode);
+
+        let referral_code = &mut ctx.accounts.referral_code;
+        referral_code.code = code;
+        referral_code.referrer = ctx.accounts.referrer.key();
+        referral_code.bump = ctx.bumps.referral_code;
+        Ok(())
+    }
+
+    // Withdraw everything accrued to the referrer in this pool
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>, pool_id: u64) -> Result<()> {
+        let amount = ctx.accounts.referrer_account.accrued;
+        require!(amount > 0, ErrorCode::NoReward);
+
+        let protocol_bump = ctx.bumps.protocol;
+        let seeds = &[b"protocol" as &[u8], &[protocol_bump]];
+        let signer = &[&seeds[..]];
+
+        ctx.accounts.referrer_account.accrued = 0;
+        ctx.accounts.pool.record_reward_payout(amount)?;
+        ctx.accounts.pool.stats.referral_paid = ctx.accounts.pool.stats.referral_paid.saturating_add(amount);
+        if ctx.accounts.pool.is_native_reward() {
+            let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+            transfer_helper::transfer_sol_out(
+                &sol_vault.to_account_info(),
+                &ctx.accounts.referrer.to_account_info(),
+                amount,
+            )?;
+        } else {
+            let reward_mint = ctx.accounts.reward_mint.as_ref().ok_or(ErrorCode::MintRequired)?;
+            let protocol_vault = ctx.accounts.protocol_vault.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            let referrer_token_account = ctx.accounts.referrer_token_account.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
+            transfer_helper::transfer_tokens_checked_signed(
+                &ctx.accounts.token_program.to_account_info(),
+                &protocol_vault.to_account_info(),
+                reward_mint,
+                &referrer_token_account.to_account_info(),
+                &ctx.accounts.protocol.to_account_info(),
+                signer,
+                amount,
+            )?;
+        }
+
+        emit!(ReferralClaimEvent {
+            referrer: ctx.accounts.referrer.key(),
+            pool_id,
+            amount,
+        });
+        Ok(())
+    }
+
+    pub fn set_referral_eligibility(
+        ctx: Context<UpdateProtocolConfig>,
+        min_stake: u64,
+        min_duration: i64,
+    ) -> Result<()> {
+        require!(min_duration >= 0, ErrorCode::InvalidPeriod);
+        ctx.accounts.protocol.referral_min_stake = min_stake;
+        ctx.accounts.protocol.referral_min_duration = min_duration;
+        Ok(())
+    }
+
+    // Point the protocol at the governance config registry that bounds its parameters
+    pub fn set_config_registry(ctx: Context<SetConfigRegistry>) -> Result<()> {
+        ctx.accounts.protocol.config_registry = ctx.accounts.config_registry.key();
+        Ok(())
+    }
+
+    pub fn initialize_masscall_allowlist(ctx: Context<InitializeMasscallAllowlist>) -> Result<()> {
+        ctx.accounts.masscall_allowlist.entries = Vec::new();
+        Ok(())
+    }
+
+    // Allow masscall to invoke `program_id` with data starting with `discriminator`
+    pub fn add_masscall_target(
+        ctx: Context<UpdateMasscallAllowlist>,
+        program_id: Pubkey,
+        discriminator: Vec<u8>,
+    ) -> Result<()> {
+        ctx.accounts.masscall_allowlist.add(program_id, discriminator)
+    }
+
+    pub fn remove_masscall_target(
+        ctx: Context<UpdateMasscallAllowlist>,
+        program_id: Pubkey,
+        discriminator: Vec<u8>,
+    ) -> Result<()> {
+        ctx.accounts.masscall_allowlist.remove(&program_id, &discriminator)
+    }
+
+    // Cap how much of `mint` the protocol PDA may send out through masscall per period
+    pub fn set_outflow_limit(
+        ctx: Context<SetOutflowLimit>,
+        mint: Pubkey,
+        amount_per_period: u64,
+        period: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.protocol.owner == ctx.accounts.authority.key() ||
+            ctx.accounts.protocol.governance == ctx.accounts.authority.key(),
+            ErrorCode::NotOwnerOrGovernance
+        );
+        require!(period > 0, ErrorCode::InvalidPeriod);
+
+        let outflow_limit = &mut ctx.accounts.outflow_limit;
+        outflow_limit.mint = mint;
+        outflow_limit.amount_per_period = amount_per_period;
+        outflow_limit.period = period;
+        outflow_limit.bump = ctx.bumps.outflow_limit;
+
+        msg!("Outflow limit for {} set to {} every {}s", mint, amount_per_period, period);
+        Ok(())
+    }
+
+    // Owner and governance may be handed to a governance multisig PDA, which signs via CPI
+    pub fn set_owner(ctx: Context<SetProtocolRole>, new_owner: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol.owner == ctx.accounts.authority.key(), ErrorCode::Unauthorized);
+        require!(new_owner != Pubkey::default(), ErrorCode::InvalidAuthority);
+
+        ctx.accounts.protocol.owner = new_owner;
+        Ok(())
+    }
+
+    // Only the current governance can hand itself over, otherwise the owner could take
+    // over everything that is meant to be governance-only
+    pub fn set_governance(ctx: Context<SetProtocolRole>, new_governance: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol.governance == ctx.accounts.authority.key(), ErrorCode::Unauthorized);
+        require!(new_governance != Pubkey::default(), ErrorCode::InvalidAuthority);
+
+        ctx.accounts.protocol.governance = new_governance;
+        Ok(())
+    }
+
     pub fn approve(
         ctx: Context<Approve>,
         user: Pubkey,
@@ -416,191 +1109,116 @@ pub mod dextra {
         ctx: Context<Masscall>,
         governance: Pubkey,
         setup_data: Vec<u8>,
-    ) -> Result<()> {
+    ) -> Result<MasscallResult> {
         // Get all the remaining accounts that were passed to this instruction
         let remaining_accounts = ctx.remaining_accounts;
-        
-        // Convert remaining_accounts to AccountMeta format for the instruction
-        // With additional signer validation
-        let mut account_metas: Vec<solana_program::instruction::AccountMeta> = Vec::new();
-        
-        // Track if we find the protocol PDA in the remaining accounts
-        let mut protocol_pda_index = None;
-        
-        // Track potential token transfer owner
-        let mut token_owner_index = None;
-        let mut token_owner_is_signer = false;
-        
-        // Determine if this is a token transfer (governance program ID matches TOKEN_PROGRAM_ID)
-        let token_program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
-        let is_token_transfer = governance == token_program_id;
-        
-        for (i, account_info) in remaining_accounts.iter().enumerate() {
-            // For token transfers, we need to identify the owner (index 2 in token instruction)
-            if is_token_transfer && i == 2 {
-                token_owner_index = Some(i);
-                token_owner_is_signer = account_info.is_signer;
-                
-                // Log information about the token owner
-                msg!("Token owner: {}, is_signer: {}", account_info.key, account_info.is_signer);
-            }
-            
-            // Special check for token transfers: Allow a signer if it's the token owner
-            if is_token_transfer && i == 2 && account_info.is_signer {
-                // For token transfers, allow the owner to be a signer
-                // But verify it's not trying to impersonate the protocol
-                if account_info.key == &ctx.accounts.protocol.key() {
-                    return Err(ErrorCode::UnauthorizedSigner.into());
-                }
-                
-                msg!("Allowing token owner as signer: {}", account_info.key);
-            } else if account_info.is_signer && account_info.key != &ctx.accounts.authority.key() {
-                // For non-token transfers or non-owner accounts, prevent unauthorized signers
-                msg!("Unauthorized signer detected: {}", account_info.key);
-                return Err(ErrorCode::UnauthorizedSigner.into());
-            }
-            
-            // Check if this is the protocol PDA that might need signing
-            if account_info.key == &ctx.accounts.protocol.key() {
-                protocol_pda_index = Some(i);
-                // Log for debugging
-                msg!("Found protocol PDA in remaining accounts at index {}", i);
-            }
-            
-            let meta = if account_info.is_writable {
-                solana_program::instruction::AccountMeta::new(
-                    *account_info.key,
-                    account_info.is_signer
-                )
-            } else {
-                solana_program::instruction::AccountMeta::new_readonly(
-                    *account_info.key,
-                    account_info.is_signer
-                )
-            };
-            account_metas.push(meta);
-        }
-        
+        let protocol_key = ctx.accounts.protocol.key();
+
+        // The protocol PDA signs whenever it is passed along
+        let sign_with_protocol = remaining_accounts
+            .iter()
+            .any(|account_info| account_info.key == &protocol_key);
+
+        let prepared = masscall_helper::prepare_call(
+            &ctx.accounts.masscall_allowlist,
+            governance,
+            setup_data,
+            remaining_accounts,
+            remaining_accounts,
+            &protocol_key,
+            &ctx.accounts.authority.key(),
+            sign_with_protocol,
+            false,
+        )?;
+
         // Get protocol PDA seeds for signing
         let protocol_bump = ctx.bumps.protocol;
         let seeds = &[b"protocol" as &[u8], &[protocol_bump]];
         let signer_seeds = &[&seeds[..]];
-        
+
         // Log some diagnostic information
         msg!("Executing CPI to program: {}", governance);
-        
-        // Special handling for token transfers
-        if is_token_transfer && remaining_accounts.len() >= 3 {
-            msg!("Token transfer detected");
-            
-            // Extract the accounts from remaining_accounts
-            let source = &remaining_accounts[0];
-            let destination = &remaining_accounts[1];
-            let owner = &remaining_accounts[2];  // The owner of the source account
-            
-            // Extract amount from setup_data (assumes setup_data is a token transfer instruction)
-            let amount = if setup_data.len() >= 9 {
-                let mut amount_bytes = [0u8; 8];
-                amount_bytes.copy_from_slice(&setup_data[1..9]);
-                u64::from_le_bytes(amount_bytes)
-            } else {
-                msg!("Invalid token transfer data");
-                return Err(ErrorCode::InvalidAmount.into());
-            };
-            
-            // Check for different token transfer scenarios
-            if owner.key == &ctx.accounts.protocol.key() {
-                // Case 1: Protocol is the owner (protocol → user transfer)
-                msg!("Protocol PDA is the token owner - needs program signing");
-                
-                // Create the instruction with our seeds
-                let ix = anchor_spl::token::spl_token::instruction::transfer(
-                    &token_program_id,
-                    source.key,
-                    destination.key,
-                    owner.key,
-                    &[],
-                    amount,
-                )?;
-                
-                // Execute with our signer seeds
-                solana_program::program::invoke_signed(
-                    &ix,
-                    &[source.clone(), destination.clone(), owner.clone()],
-                    signer_seeds,
-                )?;
-                
-                msg!("Token transfer from protocol completed successfully!");
-                return Ok(());
-            } else if owner.is_signer {
-                // Case 2: User is the owner and is signing (user → protocol or user → user transfer)
-                msg!("User is the token owner and is signing: {}", owner.key);
-                
-                // Just execute the transfer normally as the user is signing directly
-                // We don't need to sign with PDA seeds here
-                let ix = solana_program::instruction::Instruction {
-                    program_id: token_program_id,
-                    accounts: account_metas,
-                    data: setup_data,
-                };
-                
-                solana_program::program::invoke(
-                    &ix,
-                    remaining_accounts,
-                )?;
-                
-                msg!("Direct signer token transfer completed successfully!");
-                return Ok(());
-            } else {
-                // Not a supported token transfer pattern
-                msg!("Unsupported token transfer pattern: owner {} is not protocol and not a signer", owner.key);
-                return Err(ErrorCode::UnauthorizedSigner.into());
-            }
-        } else if protocol_pda_index.is_some() {
-            // For other instructions where protocol PDA was found
-            msg!("Protocol PDA will sign for account at index: {}", protocol_pda_index.unwrap());
-            
-            // Create the instruction with appropriate accounts and data
-            let ix = solana_program::instruction::Instruction {
-                program_id: governance,
-                accounts: account_metas,
-                data: setup_data,
-            };
-            
-            // Execute the instruction via CPI with PDA signing
-            solana_program::program::invoke_signed(
-                &ix,
+
+        // Inner failures are not remapped, the transaction error carries the target's own code
+        // Returned borsh-encoded, the same shape as one masscall_batch entry
+        masscall_helper::invoke_prepared(&prepared, 0, remaining_accounts, signer_seeds)
+    }
+
+    // Runs every masscall check without the CPI, meant to be called through simulateTransaction
+    pub fn simulate_masscall(
+        ctx: Context<Masscall>,
+        governance: Pubkey,
+        setup_data: Vec<u8>,
+    ) -> Result<MasscallReport> {
+        let remaining_accounts = ctx.remaining_accounts;
+        let protocol_key = ctx.accounts.protocol.key();
+        let sign_with_protocol = remaining_accounts
+            .iter()
+            .any(|account_info| account_info.key == &protocol_key);
+
+        let prepared = masscall_helper::prepare_call(
+            &ctx.accounts.masscall_allowlist,
+            governance,
+            setup_data,
+            remaining_accounts,
+            remaining_accounts,
+            &protocol_key,
+            &ctx.accounts.authority.key(),
+            sign_with_protocol,
+            true,
+        )?;
+        Ok(prepared.report)
+    }
+
+    // Executes several CPIs in order, any failure reverts the whole batch
+    pub fn masscall_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, Masscall<'info>>,
+        entries: Vec<MasscallEntry>,
+    ) -> Result<()> {
+        require!(!entries.is_empty(), ErrorCode::InvalidAmount);
+
+        let remaining_accounts = ctx.remaining_accounts;
+        let protocol_key = ctx.accounts.protocol.key();
+        let authority_key = ctx.accounts.authority.key();
+
+        let protocol_bump = ctx.bumps.protocol;
+        let seeds = &[b"protocol" as &[u8], &[protocol_bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let mut results = Vec::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let accounts = masscall_helper::select_accounts(remaining_accounts, &entry.account_index_ranges)?;
+            let prepared = masscall_helper::prepare_call(
+                &ctx.accounts.masscall_allowlist,
+                entry.program_id,
+                entry.data,
+                &accounts,
+                remaining_accounts,
+                &protocol_key,
+                &authority_key,
+                entry.sign_with_protocol,
+                false,
+            )?;
+
+            msg!("Batch entry {}: executing CPI to program {}", i, entry.program_id);
+
+            // All remaining accounts are passed so the target program account is always available
+            results.push(masscall_helper::invoke_prepared(
+                &prepared,
+                i as u8,
                 remaining_accounts,
                 signer_seeds,
-            ).map_err(|err| {
-                msg!("Failed to execute CPI call: {:?}", err);
-                error!(ErrorCode::CpiError)
-            })?;
-            
-            msg!("CPI executed successfully with protocol PDA signing!");
+            )?);
+        }
+
+        // Borsh-encoded like masscall's result. Per-entry results are always in the MasscallExecuted
+        // events, return data has a hard size cap
+        let return_data = results.try_to_vec()?;
+        if return_data.len() <= solana_program::program::MAX_RETURN_DATA {
+            solana_program::program::set_return_data(&return_data);
         } else {
-            // For regular instructions with no PDA
-            msg!("Protocol PDA not found in remaining accounts, it won't sign");
-            
-            // Create the instruction with appropriate accounts and data
-            let ix = solana_program::instruction::Instruction {
-                program_id: governance,
-                accounts: account_metas,
-                data: setup_data,
-            };
-            
-            // Execute without PDA signing
-            solana_program::program::invoke(
-                &ix,
-                remaining_accounts,
-            ).map_err(|err| {
-                msg!("Failed to execute CPI call: {:?}", err);
-                error!(ErrorCode::CpiError)
-            })?;
-            
-            msg!("CPI executed successfully without PDA signing!");
+            msg!("Batch return data is {} bytes, too large to return", return_data.len());
         }
-        
         Ok(())
     }
 
@@ -634,18 +1252,20 @@ pub mod dextra {
             };
 
             if token == Pubkey::default() {
-                transfer_helper::safe_transfer_sol(to, &ctx.accounts.protocol.to_account_info(), amount)?
+                let sol_vault = ctx.accounts.sol_vault.as_ref().ok_or(ErrorCode::SolVaultRequired)?;
+                transfer_helper::transfer_sol_out(&sol_vault.to_account_info(), to, amount)?
             } else {
                 let protocol_token = b"protocol_token";
                 let pool_key = pool.key();
                 let pool_key_ref = pool_key.as_ref();
                 let seeds = [protocol_token, pool_key_ref, &[ctx.bumps.protocol]];
                 let seeds_slice = &[&seeds[..]];
+                let protocol_vault = ctx.accounts.protocol_vault.as_ref().ok_or(ErrorCode::TokenAccountRequired)?;
                 
                 let transfer_ctx = CpiContext::new_with_signer(
                     ctx.accounts.token_program.to_account_info(),
                     Transfer {
-                        from: ctx.accounts.protocol_vault.to_account_info(),
+                        from: protocol_vault.to_account_info(),
                         to: to.clone(),
                         authority: ctx.accounts.protocol.to_account_info(),
                     },
@@ -697,18 +1317,190 @@ pub struct ReferrerEntry {
     pub referrer: Pubkey,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ReferralTier {
+    pub min_referred_stake: u64,
+    pub bonus_percent: u64, // basis points added to every level
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct UserFlagEntry {
     pub user: Pubkey,
     pub flag: bool,
 }
 
+// Half-open range [start, end) into the instruction's remaining accounts
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AccountRange {
+    pub start: u8,
+    pub end: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MasscallEntry {
+    pub program_id: Pubkey,
+    pub account_index_ranges: Vec<AccountRange>,
+    pub data: Vec<u8>,
+    pub sign_with_protocol: bool,
+}
+
+// SPL token instructions masscall knows how to route
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TokenCall {
+    Transfer { amount: u64 },
+    TransferChecked { amount: u64, decimals: u8 },
+    Approve { amount: u64 },
+    Revoke,
+    CloseAccount,
+    SyncNative,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct OutflowReport {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub spent_in_period: u64, // including this outflow
+    pub amount_per_period: u64,
+}
+
+// What a masscall does once every check has passed
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MasscallReport {
+    pub program_id: Pubkey,
+    pub sign_with_protocol: bool,
+    pub account_count: u32,
+    pub token_call: Option<TokenCall>,
+    pub outflow: Option<OutflowReport>,
+}
+
+// Outcome of one executed masscall CPI
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MasscallResult {
+    pub program_id: Pubkey,
+    pub return_data: Vec<u8>, // empty when the target returned nothing
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct AllowlistEntry {
+    pub program_id: Pubkey,
+    pub discriminator: Vec<u8>, // instruction data prefix, 8 bytes for Anchor, 1 byte for SPL token
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct RateEntry {
     pub timestamp: i64,
     pub value: u64,
 }
 
+// Running totals of a pool, in the pool's own tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct PoolStats {
+    pub active_positions: u64,
+    pub depositors: u64,             // unique wallets
+    pub rewards_paid: u64,
+    pub referral_paid: u64,
+    pub swap_volume_to_reward: u64,  // deposit tokens swapped in
+    pub swap_volume_to_deposit: u64, // reward tokens swapped in
+}
+
+impl PoolStats {
+    pub const LEN: usize = 8 + // active_positions
+        8 + // depositors
+        8 + // rewards_paid
+        8 + // referral_paid
+        8 + // swap_volume_to_reward
+        8; // swap_volume_to_deposit
+}
+
+// Upper bounds on what a pool takes in, 0 leaves a bound off
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct DepositCaps {
+    pub max_per_user: u64,
+    pub max_total_deposits: u64,
+    pub max_per_deposit: u64,
+}
+
+impl DepositCaps {
+    pub const LEN: usize = 8 + // max_per_user
+        8 + // max_total_deposits
+        8; // max_per_deposit
+
+    // `amount` is what arrives on top of the wallet's and the pool's current deposits
+    pub fn check(&self, amount: u64, wallet_amount: u64, total_deposited: u64) -> Result<()> {
+        require!(
+            self.max_per_deposit == 0 || amount <= self.max_per_deposit,
+            ErrorCode::DepositAboveMaximum
+        );
+        let user_after = wallet_amount.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        require!(
+            self.max_per_user == 0 || user_after <= self.max_per_user,
+            ErrorCode::UserDepositCapExceeded
+        );
+        let total_after = total_deposited.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        require!(
+            self.max_total_deposits == 0 || total_after <= self.max_total_deposits,
+            ErrorCode::PoolDepositCapExceeded
+        );
+        Ok(())
+    }
+}
+
+// A reward token paid on top of the pool's base reward, from its own vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct RewardStream {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub total_paid: u64,
+    pub start_timestamp: i64,
+    pub last_rate: u64,
+    pub last_apy: u64,
+    pub rates: Vec<RateEntry>,
+    pub apys: Vec<RateEntry>,
+}
+
+impl RewardStream {
+    pub const LEN: usize = 32 + // mint
+        32 + // vault
+        8 + // total_paid
+        8 + // start_timestamp
+        8 + // last_rate
+        8 + // last_apy
+        512 + // rates vector (estimated size)
+        512; // apys vector (estimated size)
+
+    fn get_rate(&self, timestamp: i64) -> u64 {
+        self.rates
+            .iter()
+            .find(|entry| entry.timestamp == timestamp)
+            .map(|entry| entry.value)
+            .unwrap_or(self.last_rate)
+    }
+
+    fn get_apy(&self, timestamp: i64) -> u64 {
+        self.apys
+            .iter()
+            .find(|entry| entry.timestamp == timestamp)
+            .map(|entry| entry.value)
+            .unwrap_or(self.last_apy)
+    }
+
+    fn set_rate(&mut self, timestamp: i64, rate: u64) {
+        self.rates.push(RateEntry { timestamp, value: rate });
+        self.last_rate = rate;
+    }
+
+    fn set_apy(&mut self, timestamp: i64, apy: u64) {
+        self.apys.push(RateEntry { timestamp, value: apy });
+        self.last_apy = apy;
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct RewardAmount {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
 #[account]
 #[derive(Default)]
 pub struct ProtocolAccount {
@@ -716,6 +1508,9 @@ pub struct ProtocolAccount {
     pub governance: Pubkey,
     pub ref_percent: u64,
     pub pool_count: u64,
+    pub config_registry: Pubkey,
+    pub referral_min_stake: u64,    // referee stake before their referrers earn
+    pub referral_min_duration: i64, // seconds the referee must have been staking
     // Replacing tuple vectors with struct vectors
     pub referrers: Vec<ReferrerEntry>,         // (user, referrer)
     pub claimable_users: Vec<UserFlagEntry>,   // (user, can_claim)
@@ -728,6 +1523,9 @@ impl ProtocolAccount {
         32 +    // governance pubkey
         8 +     // ref_percent
         8 +     // pool_count
+        32 +    // config_registry pubkey
+        8 +     // referral_min_stake
+        8 +     // referral_min_duration
         1024 +  // space for referrers vector
         512 +   // space for claimable_users vector
         512;    // space for withdrawable_users vector
@@ -738,6 +1536,9 @@ impl ProtocolAccount {
             governance: Pubkey::default(),
             ref_percent: 0,
             pool_count: 0,
+            config_registry: Pubkey::default(),
+            referral_min_stake: 0,
+            referral_min_duration: 0,
             referrers: Vec::new(),
             claimable_users: Vec::new(),
             withdrawable_users: Vec::new(),
@@ -770,11 +1571,23 @@ impl ProtocolAccount {
 
     pub fn setup_referrer(&mut self, user: Pubkey, referrer: Pubkey) -> Result<()> {
         if self.get_referrer(&user).is_none() && referrer != Pubkey::default() {
+            require_keys_neq!(user, referrer, ErrorCode::SelfReferral);
+            require!(self.get_referrer(&referrer) != Some(user), ErrorCode::ReferralCycle);
             self.referrers.push(ReferrerEntry { user, referrer });
         }
         Ok(())
     }
 
+    // A referee only earns their referrers rewards once past both minimums, zero disables a check
+    pub fn is_referral_eligible(&self, user_info: &UserInfo, now: i64) -> bool {
+        let staked_for = if user_info.stake_timestamp == 0 {
+            0
+        } else {
+            now.saturating_sub(user_info.stake_timestamp)
+        };
+        user_info.amount >= self.referral_min_stake && staked_for >= self.referral_min_duration
+    }
+
     pub fn set_withdrawable(&mut self, user: Pubkey, can_withdraw: bool) {
         if let Some(pos) = self.withdrawable_users.iter().position(|entry| entry.user == user) {
             self.withdrawable_users[pos] = UserFlagEntry { user, flag: can_withdraw };
@@ -792,6 +1605,215 @@ impl ProtocolAccount {
     }
 }
 
+#[account]
+#[derive(Default)]
+pub struct ReferralConfig {
+    pub pool: Pubkey,
+    pub level_percents: Vec<u64>,                 // basis points, index 0 is the direct referrer
+    pub tiers: Vec<ReferralTier>,                 // ascending by min_referred_stake
+    pub bump: u8,
+}
+
+impl ReferralConfig {
+    pub const MAX_LEVELS: usize = 5;
+    pub const MAX_TIERS: usize = 4;
+    pub const LEN: usize = 8 +    // discriminator
+        32 +                      // pool
+        4 + Self::MAX_LEVELS * 8 + // level_percents
+        4 + Self::MAX_TIERS * 16 + // tiers
+        1;                        // bump
+
+    pub fn configure(&mut self, level_percents: Vec<u64>, tiers: Vec<ReferralTier>) -> Result<()> {
+        require!(
+            !level_percents.is_empty() && level_percents.len() <= Self::MAX_LEVELS,
+            ErrorCode::InvalidReferralConfig
+        );
+        require!(tiers.len() <= Self::MAX_TIERS, ErrorCode::InvalidReferralConfig);
+        require!(
+            tiers.windows(2).all(|pair| pair[0].min_referred_stake < pair[1].min_referred_stake),
+            ErrorCode::InvalidReferralConfig
+        );
+
+        let max_total = Self::max_total_percent(&level_percents, &tiers)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        require!(max_total <= MAX_TOTAL_REF_PERCENT, ErrorCode::ReferralPercentTooHigh);
+
+        self.level_percents = level_percents;
+        self.tiers = tiers;
+        Ok(())
+    }
+
+    // Share of a claim paid to a full chain of referrers that all reached the top tier
+    pub fn max_total_percent(level_percents: &[u64], tiers: &[ReferralTier]) -> Option<u64> {
+        let max_bonus = tiers.iter().map(|tier| tier.bonus_percent).max().unwrap_or(0);
+        level_percents.iter().try_fold(0u64, |total, percent| {
+            total.checked_add(*percent)?.checked_add(max_bonus)
+        })
+    }
+
+    // Level percent plus the bonus of the highest tier the referrer reached
+    pub fn percent_for(&self, level: usize, referred_stake: u64) -> u64 {
+        let bonus = self.tiers
+            .iter()
+            .rev()
+            .find(|tier| referred_stake >= tier.min_referred_stake)
+            .map(|tier| tier.bonus_percent)
+            .unwrap_or(0);
+        self.level_percents[level] + bonus
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct ReferralCode {
+    pub code: String,
+    pub referrer: Pubkey,
+    pub bump: u8,
+}
+
+impl ReferralCode {
+    pub const MAX_CODE_LEN: usize = 16;
+    pub const LEN: usize = 8 + // discriminator
+        4 + Self::MAX_CODE_LEN + // code
+        32 + // referrer
+        1; // bump
+
+    // Letters, digits, '-' and '_', so codes survive URLs and copy-paste
+    pub fn is_valid(code: &str) -> bool {
+        !code.is_empty()
+            && code.len() <= Self::MAX_CODE_LEN
+            && code.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    }
+}
+
+// Referral earnings and stats of one referrer in one pool
+#[account]
+#[derive(Default)]
+pub struct ReferrerAccount {
+    pub referrer: Pubkey,
+    pub pool: Pubkey,
+    pub referee_count: u64,
+    pub total_referred_stake: u64,
+    pub accrued: u64,      // earned but not yet claimed
+    pub total_earned: u64,
+    pub bump: u8,
+}
+
+impl ReferrerAccount {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // referrer
+        32 + // pool
+        8 + // referee_count
+        8 + // total_referred_stake
+        8 + // accrued
+        8 + // total_earned
+        1; // bump
+
+    pub fn record_referral(&mut self, stake: u64, new_referee: bool) -> Result<()> {
+        if new_referee {
+            self.referee_count = self.referee_count.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+        }
+        self.total_referred_stake = self.total_referred_stake
+            .checked_add(stake)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        Ok(())
+    }
+
+    // Positions from before referred stake was tracked may release more than was recorded
+    pub fn release_referral(&mut self, stake: u64) {
+        self.total_referred_stake = self.total_referred_stake.saturating_sub(stake);
+    }
+
+    pub fn accrue(&mut self, amount: u64) -> Result<()> {
+        self.accrued = self.accrued.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        self.total_earned = self.total_earned.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct MasscallAllowlist {
+    pub entries: Vec<AllowlistEntry>,
+}
+
+impl MasscallAllowlist {
+    pub const MAX_ENTRIES: usize = 32;
+    pub const MAX_DISCRIMINATOR_LEN: usize = 8;
+    pub const LEN: usize = 8 +    // discriminator
+        4 +                       // vec length prefix
+        Self::MAX_ENTRIES * (32 + 4 + Self::MAX_DISCRIMINATOR_LEN);
+
+    pub fn is_allowed(&self, program_id: &Pubkey, data: &[u8]) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| &entry.program_id == program_id && data.starts_with(&entry.discriminator))
+    }
+
+    pub fn add(&mut self, program_id: Pubkey, discriminator: Vec<u8>) -> Result<()> {
+        require!(
+            !discriminator.is_empty() && discriminator.len() <= Self::MAX_DISCRIMINATOR_LEN,
+            ErrorCode::InvalidDiscriminator
+        );
+        if self.entries
+            .iter()
+            .any(|entry| entry.program_id == program_id && entry.discriminator == discriminator)
+        {
+            return Ok(());
+        }
+        require!(self.entries.len() < Self::MAX_ENTRIES, ErrorCode::AllowlistFull);
+        self.entries.push(AllowlistEntry { program_id, discriminator });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, program_id: &Pubkey, discriminator: &[u8]) -> Result<()> {
+        let pos = self.entries
+            .iter()
+            .position(|entry| &entry.program_id == program_id && entry.discriminator == discriminator)
+            .ok_or(ErrorCode::MasscallTargetNotAllowed)?;
+        self.entries.remove(pos);
+        Ok(())
+    }
+}
+
+// Rolling cap on tokens the protocol PDA signs out through masscall, reset every `period` seconds
+#[account]
+pub struct OutflowLimit {
+    pub mint: Pubkey,
+    pub amount_per_period: u64,
+    pub period: i64,
+    pub period_start: i64,
+    pub spent_in_period: u64,
+    pub bump: u8,
+}
+
+impl OutflowLimit {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // mint
+        8 + // amount_per_period
+        8 + // period
+        8 + // period_start
+        8 + // spent_in_period
+        1; // bump
+
+    pub fn consume(&mut self, amount: u64, now: i64) -> Result<()> {
+        let period_end = self.period_start
+            .checked_add(self.period)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        if now >= period_end {
+            self.period_start = now;
+            self.spent_in_period = 0;
+        }
+
+        let spent = self.spent_in_period
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        require!(spent <= self.amount_per_period, ErrorCode::OutflowLimitExceeded);
+        self.spent_in_period = spent;
+        Ok(())
+    }
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("Pool does not exist")]
@@ -828,6 +1850,109 @@ pub enum ErrorCode {
     UnauthorizedSigner,
     #[msg("Invalid program ID")]
     InvalidProgramId,
+    #[msg("Config registry account required")]
+    ConfigRegistryRequired,
+    #[msg("Config registry does not match the protocol")]
+    InvalidConfigRegistry,
+    #[msg("Value exceeds the governance configured limit")]
+    ConfigLimitExceeded,
+    #[msg("Account range is empty or out of bounds")]
+    InvalidAccountRange,
+    #[msg("Masscall target program or instruction is not on the allowlist")]
+    MasscallTargetNotAllowed,
+    #[msg("Discriminator must be between 1 and 8 bytes")]
+    InvalidDiscriminator,
+    #[msg("Masscall allowlist is full")]
+    AllowlistFull,
+    #[msg("Unsupported token instruction")]
+    UnsupportedTokenInstruction,
+    #[msg("Token instruction accounts do not match the instruction")]
+    InvalidTokenInstructionAccounts,
+    #[msg("Period must be positive")]
+    InvalidPeriod,
+    #[msg("No outflow limit configured for this mint")]
+    OutflowLimitRequired,
+    #[msg("Protocol outflow limit exceeded for this period")]
+    OutflowLimitExceeded,
+    #[msg("Invalid referral config")]
+    InvalidReferralConfig,
+    #[msg("Referral percentages exceed the total cap")]
+    ReferralPercentTooHigh,
+    #[msg("Invalid referrer account")]
+    InvalidReferrerAccount,
+    #[msg("Users cannot refer themselves")]
+    SelfReferral,
+    #[msg("Referrer is already referred by this user")]
+    ReferralCycle,
+    #[msg("Invalid referral code")]
+    InvalidReferralCode,
+    #[msg("Native SOL pools need their SOL vault")]
+    SolVaultRequired,
+    #[msg("SPL token pools need their token accounts")]
+    TokenAccountRequired,
+    #[msg("SOL vault balance is too low")]
+    InsufficientVaultBalance,
+    #[msg("Mint does not match the pool")]
+    InvalidMint,
+    #[msg("SPL token pools need their mint")]
+    MintRequired,
+    #[msg("Mint has a Token-2022 extension the protocol does not support")]
+    UnsupportedMintExtension,
+    #[msg("Pool already has the maximum number of reward streams")]
+    RewardStreamLimit,
+    #[msg("Pool already pays this reward token")]
+    DuplicateRewardStream,
+    #[msg("Reward stream does not exist")]
+    InvalidRewardStream,
+    #[msg("Reward vault does not match the reward stream")]
+    InvalidRewardVault,
+    #[msg("Accounts for every reward stream are required")]
+    RewardStreamAccountsRequired,
+    #[msg("Not supported in this pool's accounting mode")]
+    InvalidAccountingMode,
+    #[msg("Deposit would leave the pool unable to pay its rewards")]
+    PoolInsolvent,
+    #[msg("Reward budget exhausted")]
+    RewardBudgetExhausted,
+    #[msg("Pool is not accepting deposits")]
+    PoolNotOpen,
+    #[msg("Pool is closed")]
+    PoolClosed,
+    #[msg("Pool has to be sunset first")]
+    PoolNotClosed,
+    #[msg("Pool still has deposits")]
+    PoolNotEmpty,
+    #[msg("Invalid pool schedule")]
+    InvalidPoolSchedule,
+    #[msg("Deposit is above the per-deposit maximum")]
+    DepositAboveMaximum,
+    #[msg("Deposit would exceed the per-user cap")]
+    UserDepositCapExceeded,
+    #[msg("Deposit would exceed the pool's cap")]
+    PoolDepositCapExceeded,
+    #[msg("Pool has a cooldown, use request_unstake")]
+    UnbondingRequired,
+    #[msg("Too many pending unstake requests")]
+    UnstakeRequestLimit,
+    #[msg("Unstake request not found")]
+    UnstakeRequestNotFound,
+    #[msg("Cooldown has not elapsed yet")]
+    CooldownNotElapsed,
+    #[msg("Invalid cooldown")]
+    InvalidCooldown,
+    #[msg("User info belongs to another pool")]
+    InvalidUserInfoPool,
+    #[msg("Referrer account required")]
+    ReferrerAccountRequired,
+
+    #[msg("Token account is not the protocol's account for this mint")]
+    InvalidProtocolTokenAccount,
+
+    #[msg("Pool referral config required")]
+    ReferralConfigRequired,
+
+    #[msg("Rewards are still owed to positions or referrers")]
+    RewardsOutstanding,
 }
 
 #[account]
@@ -842,6 +1967,35 @@ pub struct Pool {
     pub last_apy: u64,
     pub rates: Vec<RateEntry>,  // Replacing (timestamp, rate) tuples
     pub apys: Vec<RateEntry>,   // Replacing (timestamp, apy) tuples
+    pub reward_streams: Vec<RewardStream>,
+    pub accounting_mode: AccountingMode,
+    pub emission_per_second: u64,       // shared emissions only
+    pub acc_reward_per_share: u128,     // shared emissions only, scaled by REWARD_PER_SHARE_PRECISION
+    pub last_reward_timestamp: i64,     // shared emissions only
+    pub total_deposited: u64,
+    pub reward_budget: u64,             // funded reward tokens not yet paid out, streams excluded
+    pub reward_liabilities: u64,        // estimated rewards accrued to positions but not yet claimed
+    pub liabilities_updated_at: i64,
+    pub budget_enforced: bool,          // set by the first `fund_rewards`
+    pub start_ts: i64,                  // deposits open
+    pub end_ts: i64,                    // deposits close and rewards stop, 0 runs forever
+    pub closed: bool,                   // sunset, only withdrawals and claims are left
+    pub deposit_caps: DepositCaps,
+    pub stats: PoolStats,
+    pub cooldown: i64,                  // unbonding period, 0 allows plain withdrawals
+    pub total_unbonding: u64,           // requested unstakes not yet completed
+    pub has_referral_config: bool,      // claims must then pass the pool's referral config
+    pub rewards_owed: u64,              // credited to positions and referrers, not yet claimed
+}
+
+// How a pool turns deposits into rewards
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum AccountingMode {
+    // Every position earns the pool's APY, whatever the pool holds
+    #[default]
+    FixedApy,
+    // A fixed emission per second is split pro-rata over everything deposited
+    SharedEmissions,
 }
 
 impl Pool {
@@ -853,36 +2007,346 @@ impl Pool {
         8 +     // last_rate
         8 +     // last_apy
         512 +   // rates vector (estimated size)
-        512;    // apys vector (estimated size)
+        512 +   // apys vector (estimated size)
+        4 + Self::MAX_REWARD_STREAMS * RewardStream::LEN + // reward_streams
+        1 +     // accounting_mode
+        8 +     // emission_per_second
+        16 +    // acc_reward_per_share
+        8 +     // last_reward_timestamp
+        8 +     // total_deposited
+        8 +     // reward_budget
+        8 +     // reward_liabilities
+        8 +     // liabilities_updated_at
+        1 +     // budget_enforced
+        8 +     // start_ts
+        8 +     // end_ts
+        1 +     // closed
+        DepositCaps::LEN + // deposit_caps
+        PoolStats::LEN +   // stats
+        8 +     // cooldown
+        8 +     // total_unbonding
+        1 +     // has_referral_config
+        8;      // rewards_owed
+
+    // Extra reward tokens a pool can pay besides `reward_token`
+    pub const MAX_REWARD_STREAMS: usize = 3;
         
-    fn get_rate(&self, timestamp: i64) -> u64 {
-        self.rates
-            .iter()
-            .find(|entry| entry.timestamp == timestamp)
-            .map(|entry| entry.value)
-            .unwrap_or(self.last_rate)
+    // Nothing accrues past the end of the pool
+    pub fn reward_cutoff(&self, timestamp: i64) -> i64 {
+        if self.end_ts > 0 {
+            timestamp.min(self.end_ts)
+        } else {
+            timestamp
+        }
     }
-    
-    fn get_apy(&self, timestamp: i64) -> u64 {
-        self.apys
-            .iter()
-            .find(|entry| entry.timestamp == timestamp)
-            .map(|entry| entry.value)
-            .unwrap_or(self.last_apy)
+
+    // APY in reward tokens, same scale as `last_apy`. A shared-emissions pool's depends on
+    // how much is deposited and assumes both tokens are worth the same.
+    pub fn effective_apy(&self, day: i64) -> u64 {
+        match self.accounting_mode {
+            AccountingMode::FixedApy => {
+                (self.get_apy(day) as u128 * self.get_rate(day) as u128 / 1_000_000) as u64
+            }
+            AccountingMode::SharedEmissions => {
+                if self.total_deposited == 0 {
+                    return 0;
+                }
+                let yearly = self.emission_per_second as u128 * 365 * 86400 * 10_000;
+                u64::try_from(yearly / self.total_deposited as u128).unwrap_or(u64::MAX)
+            }
+        }
     }
-    
-    fn set_rate(&mut self, timestamp: i64, rate: u64) {
-        // Always append a new entry instead of replacing existing ones
-        self.rates.push(RateEntry { timestamp, value: rate });
-        self.last_rate = rate;
+
+    pub fn is_open(&self, timestamp: i64) -> bool {
+        !self.closed && timestamp >= self.start_ts && (self.end_ts == 0 || timestamp < self.end_ts)
     }
-    
-    fn set_apy(&mut self, timestamp: i64, apy: u64) {
-        // Always append a new entry instead of replacing existing ones
-        self.apys.push(RateEntry { timestamp, value: apy });
-        self.last_apy = apy;
+
+    // Reward per deposited token accumulated up to `timestamp`, without updating the pool
+    pub fn acc_reward_per_share_at(&self, timestamp: i64) -> Result<u128> {
+        let timestamp = self.reward_cutoff(timestamp);
+        if self.accounting_mode != AccountingMode::SharedEmissions
+            || self.total_deposited == 0
+            || timestamp <= self.last_reward_timestamp
+        {
+            return Ok(self.acc_reward_per_share);
+        }
+
+        let elapsed = (timestamp - self.last_reward_timestamp) as u128;
+        let emitted = elapsed
+            .checked_mul(self.emission_per_second as u128)
+            .and_then(|value| value.checked_mul(REWARD_PER_SHARE_PRECISION))
+            .ok_or(ErrorCode::ArithmeticError)?;
+        self.acc_reward_per_share
+            .checked_add(emitted / self.total_deposited as u128)
+            .ok_or(ErrorCode::ArithmeticError.into())
     }
-}
+
+    // Has to run before total_deposited changes
+    pub fn update_emissions(&mut self, timestamp: i64) -> Result<()> {
+        self.acc_reward_per_share = self.acc_reward_per_share_at(timestamp)?;
+        self.last_reward_timestamp = self.last_reward_timestamp.max(timestamp);
+        Ok(())
+    }
+
+    // What `amount` would have earned since the pool started, subtracted from future accruals
+    pub fn reward_debt_for(&self, amount: u64) -> Result<u128> {
+        (amount as u128)
+            .checked_mul(self.acc_reward_per_share)
+            .map(|value| value / REWARD_PER_SHARE_PRECISION)
+            .ok_or(ErrorCode::ArithmeticError.into())
+    }
+
+    // Rewards `total` deposits earn over `seconds` at the current rate. Only an estimate for
+    // fixed-APY pools, whose rate and APY may change from day to day.
+    pub fn estimate_rewards(&self, total: u64, seconds: i64) -> Result<u64> {
+        let seconds = seconds.max(0) as u128;
+        match self.accounting_mode {
+            AccountingMode::SharedEmissions => {
+                if total == 0 {
+                    return Ok(0);
+                }
+                let emitted = seconds
+                    .checked_mul(self.emission_per_second as u128)
+                    .ok_or(ErrorCode::ArithmeticError)?;
+                u64::try_from(emitted).map_err(|_| ErrorCode::ArithmeticError.into())
+            }
+            AccountingMode::FixedApy => {
+                let yield_amount = (total as u128)
+                    .checked_mul(seconds)
+                    .and_then(|value| value.checked_mul(self.last_apy as u128))
+                    .map(|value| value / (100u128 * 365u128 * 86400u128 * 100u128))
+                    .ok_or(ErrorCode::ArithmeticError)?;
+                let time_reward = yield_amount
+                    .checked_mul(self.last_rate as u128)
+                    .map(|value| value / 1_000_000)
+                    .ok_or(ErrorCode::ArithmeticError)?;
+                let time_reward = u64::try_from(time_reward).map_err(|_| ErrorCode::ArithmeticError)?;
+                adjust_decimals(time_reward, safe_decimals(&self.deposit_token)?, safe_decimals(&self.reward_token)?)
+            }
+        }
+    }
+
+    // Seconds the pool's solvency has to be planned for
+    pub fn remaining_term(&self, timestamp: i64) -> i64 {
+        if self.end_ts > 0 {
+            (self.end_ts - timestamp).max(0)
+        } else {
+            SOLVENCY_HORIZON
+        }
+    }
+
+    // Has to run before total_deposited changes
+    pub fn accrue_liabilities(&mut self, timestamp: i64) -> Result<()> {
+        let timestamp = self.reward_cutoff(timestamp);
+        if self.liabilities_updated_at > 0 && timestamp > self.liabilities_updated_at {
+            let accrued = self.estimate_rewards(self.total_deposited, timestamp - self.liabilities_updated_at)?;
+            self.reward_liabilities = self.reward_liabilities.checked_add(accrued).ok_or(ErrorCode::ArithmeticError)?;
+        }
+        self.liabilities_updated_at = self.liabilities_updated_at.max(timestamp);
+        Ok(())
+    }
+
+    // Rewards paid out of the vault leave both the budget and the liabilities
+    pub fn record_reward_payout(&mut self, amount: u64) -> Result<()> {
+        if self.budget_enforced {
+            require!(amount <= self.reward_budget, ErrorCode::RewardBudgetExhausted);
+        }
+        self.reward_budget = self.reward_budget.saturating_sub(amount);
+        self.reward_liabilities = self.reward_liabilities.saturating_sub(amount);
+        self.rewards_owed = self.rewards_owed.saturating_sub(amount);
+        Ok(())
+    }
+
+    // Referral shares are owed from the moment they accrue until the referrer claims them
+    pub fn record_referral_accrual(&mut self, amount: u64) -> Result<()> {
+        self.reward_liabilities = self.reward_liabilities.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        self.rewards_owed = self.rewards_owed.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        Ok(())
+    }
+
+    // Native SOL is recorded as the default pubkey in place of a mint
+    pub fn is_native_deposit(&self) -> bool {
+        self.deposit_token == Pubkey::default()
+    }
+
+    pub fn is_native_reward(&self) -> bool {
+        self.reward_token == Pubkey::default()
+    }
+
+    pub fn has_native_side(&self) -> bool {
+        self.is_native_deposit() || self.is_native_reward()
+    }
+
+    fn get_rate(&self, timestamp: i64) -> u64 {
+        self.rates
+            .iter()
+            .find(|entry| entry.timestamp == timestamp)
+            .map(|entry| entry.value)
+            .unwrap_or(self.last_rate)
+    }
+    
+    fn get_apy(&self, timestamp: i64) -> u64 {
+        self.apys
+            .iter()
+            .find(|entry| entry.timestamp == timestamp)
+            .map(|entry| entry.value)
+            .unwrap_or(self.last_apy)
+    }
+    
+    fn set_rate(&mut self, timestamp: i64, rate: u64) {
+        // Always append a new entry instead of replacing existing ones
+        self.rates.push(RateEntry { timestamp, value: rate });
+        self.last_rate = rate;
+    }
+    
+    fn set_apy(&mut self, timestamp: i64, apy: u64) {
+        // Always append a new entry instead of replacing existing ones
+        self.apys.push(RateEntry { timestamp, value: apy });
+        self.last_apy = apy;
+    }
+}
+
+// Protocol-wide counters. Token amounts (deposited, rewards and referrals paid, swap
+// volume) are only kept per pool in `PoolStats`, pools are in different tokens and a
+// protocol-wide sum would add them up regardless.
+#[account]
+#[derive(Default)]
+pub struct ProtocolStats {
+    pub active_positions: u64,
+    pub depositors: u64, // unique wallets across every pool
+    pub bump: u8,
+}
+
+impl ProtocolStats {
+    pub const LEN: usize = 8 + // discriminator
+        8 + // active_positions
+        8 + // depositors
+        1; // bump
+}
+
+// Marks a wallet that has deposited anywhere in the protocol
+#[account]
+#[derive(Default)]
+pub struct Depositor {
+    pub user: Pubkey,
+    pub bump: u8,
+}
+
+impl Depositor {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // user
+        1; // bump
+}
+
+// Everything one wallet has staked in a pool, across all of its user infos. Positions
+// from before it existed aren't counted, and leaving it out of a withdrawal only keeps
+// the wallet's own cap tighter.
+#[account]
+#[derive(Default)]
+pub struct WalletPosition {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub referee_counted: bool, // counted in the referrer's referee_count for this pool
+    pub bump: u8,
+}
+
+impl WalletPosition {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool
+        32 + // user
+        8 + // amount
+        1 + // referee_counted
+        1; // bump
+
+    pub fn release(&mut self, amount: u64) {
+        self.amount = self.amount.saturating_sub(amount);
+    }
+}
+
+// One day of a pool's figures, taken when the day is first touched. Counters are
+// running totals, a day's activity is the difference to the next snapshot.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct DailySnapshot {
+    pub day: i64,
+    pub total_deposited: u64,
+    pub rewards_paid: u64,
+    pub swap_volume_to_reward: u64,
+    pub swap_volume_to_deposit: u64,
+    pub effective_apy: u64,
+}
+
+impl DailySnapshot {
+    pub const LEN: usize = 8 + // day
+        8 + // total_deposited
+        8 + // rewards_paid
+        8 + // swap_volume_to_reward
+        8 + // swap_volume_to_deposit
+        8; // effective_apy
+}
+
+// Ring buffer of a pool's last HISTORY_DAYS daily snapshots
+#[account]
+#[derive(Default)]
+pub struct PoolHistory {
+    pub pool: Pubkey,
+    pub bump: u8,
+    pub next_index: u16,
+    pub snapshots: Vec<DailySnapshot>,
+}
+
+impl PoolHistory {
+    pub const HISTORY_DAYS: usize = 30;
+
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool
+        1 + // bump
+        2 + // next_index
+        4 + Self::HISTORY_DAYS * DailySnapshot::LEN; // snapshots
+
+    pub fn last_day(&self) -> Option<i64> {
+        let last = (self.next_index as usize + Self::HISTORY_DAYS - 1) % Self::HISTORY_DAYS;
+        self.snapshots.get(last).map(|snapshot| snapshot.day)
+    }
+
+    // Writes the pool's figures once per day, overwriting the oldest day when full
+    pub fn record(&mut self, pool: &Pool, timestamp: i64) {
+        let day = date_helper::get_start_of_date(timestamp);
+        if self.last_day().is_some_and(|last_day| last_day >= day) {
+            return;
+        }
+
+        let snapshot = DailySnapshot {
+            day,
+            total_deposited: pool.total_deposited,
+            rewards_paid: pool.stats.rewards_paid,
+            swap_volume_to_reward: pool.stats.swap_volume_to_reward,
+            swap_volume_to_deposit: pool.stats.swap_volume_to_deposit,
+            effective_apy: pool.effective_apy(day),
+        };
+        let index = self.next_index as usize;
+        if index < self.snapshots.len() {
+            self.snapshots[index] = snapshot;
+        } else {
+            self.snapshots.push(snapshot);
+        }
+        self.next_index = ((index + 1) % Self::HISTORY_DAYS) as u16;
+    }
+}
+
+// Holds the native SOL of a pool, deposits and rewards alike
+#[account]
+#[derive(Default)]
+pub struct SolVault {
+    pub pool: Pubkey,
+    pub bump: u8,
+}
+
+impl SolVault {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool
+        1; // bump
+}
 
 // Update UserInfo struct
 #[account]
@@ -896,6 +2360,11 @@ pub struct UserInfo {
     pub referrer: Pubkey,
     pub total_claimed: u64,
     pub deposits: Vec<UserDeposit>,
+    pub pending_stream_rewards: Vec<u64>, // indexed like the pool's reward streams
+    pub reward_debt: u128, // shared-emissions pools only
+    pub next_unstake_id: u64,
+    pub unstake_requests: Vec<UnstakeRequest>, // pending only, completed ones are removed
+    pub pool: Pubkey, // set by the first deposit
 }
 
 impl UserInfo {
@@ -908,7 +2377,30 @@ impl UserInfo {
         32 + // referrer
         8 + // total_claimed
         4 + // vec length prefix
-        100 * std::mem::size_of::<UserDeposit>(); // space for 100 deposits
+        100 * std::mem::size_of::<UserDeposit>() + // space for 100 deposits
+        4 + Pool::MAX_REWARD_STREAMS * 8 + // pending_stream_rewards
+        16 + // reward_debt
+        8 + // next_unstake_id
+        4 + Self::MAX_UNSTAKE_REQUESTS * UnstakeRequest::LEN + // unstake_requests
+        32; // pool
+
+    pub const MAX_UNSTAKE_REQUESTS: usize = 5;
+}
+
+// Funds on their way out of an unbonding pool
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct UnstakeRequest {
+    pub id: u64,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub available_at: i64,
+}
+
+impl UnstakeRequest {
+    pub const LEN: usize = 8 + // id
+        32 + // pool
+        8 + // amount
+        8; // available_at
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
@@ -936,6 +2428,15 @@ pub struct WithdrawEvent {
     pub amount: u64,
 }
 
+#[event]
+pub struct UnstakeRequestedEvent {
+    pub user: Pubkey,
+    pub pool_id: u64,
+    pub request_id: u64,
+    pub amount: u64,
+    pub available_at: i64,
+}
+
 #[event]
 pub struct SwapEvent {
     pub user: Pubkey,
@@ -945,144 +2446,580 @@ pub struct SwapEvent {
     pub received_amount: u64,
 }
 
-#[event]
-pub struct ClaimEvent {
-    pub user: Pubkey,
-    pub pool_id: u64,
-    pub amount: u64,
+#[event]
+pub struct ClaimEvent {
+    pub user: Pubkey,
+    pub pool_id: u64,
+    pub amount: u64,
+    pub rewards: Vec<RewardAmount>, // base reward first, then every reward stream
+}
+
+#[event]
+pub struct RewardsFundedEvent {
+    pub pool_id: u64,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub reward_budget: u64,
+}
+
+#[event]
+pub struct ReferralRewardEvent {
+    pub referrer: Pubkey,
+    pub referee: Pubkey,
+    pub pool_id: u64,
+    pub level: u8,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ReferralClaimEvent {
+    pub referrer: Pubkey,
+    pub pool_id: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct MasscallExecuted {
+    pub program_id: Pubkey,
+    pub call_index: u8,
+    pub sign_with_protocol: bool,
+    pub return_data: Vec<u8>,
+}
+
+#[event]
+pub struct ProtocolOutflowEvent {
+    pub mint: Pubkey,
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub spent_in_period: u64,
+}
+
+// Account validation structures
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init, 
+        payer = owner, 
+        space = ProtocolAccount::LEN,
+        seeds = [b"protocol"],
+        bump
+    )]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(init, payer = owner, space = UserInfo::LEN)]
+    pub user_info: Account<'info, UserInfo>,
+    #[account(init, payer = owner, space = ProtocolStats::LEN, seeds = [b"protocol_stats"], bump)]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocolStats<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(init, payer = authority, space = ProtocolStats::LEN, seeds = [b"protocol_stats"], bump)]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(
+        mut,
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    minimum_deposit: u64,
+    lock_period: i64,
+    can_swap: bool,
+    rate: u64,
+    apy: u64,
+    accounting_mode: AccountingMode
+)]
+pub struct AddPool<'info> {
+    #[account(mut)]
+    pub protocol: Account<'info, ProtocolAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Pool::LEN,
+        seeds = [b"pool", protocol.key().as_ref(), &protocol.pool_count.to_le_bytes()],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    // Left out for native SOL
+    pub deposit_token: Option<InterfaceAccount<'info, Mint>>,
+    pub reward_token: Option<InterfaceAccount<'info, Mint>>,
+
+    // Only for pools with a native SOL side
+    #[account(
+        init,
+        payer = payer,
+        space = SolVault::LEN,
+        seeds = [b"sol_vault", pool.key().as_ref()],
+        bump
+    )]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    pub config_registry: Option<Account<'info, ConfigRegistry>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ViewUserDeposit<'info> {
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(
+        constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority,
+    )]
+    pub user_info: Account<'info, UserInfo>,
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ViewUserInfo<'info> {
+    #[account(
+        constraint = user_info.authority == user.key()
+    )]
+    pub user_info: Account<'info, UserInfo>,
+    pub user: Signer<'info>,
+}
+
+
+
+
+// Complete date_helper module implementation
+pub mod date_helper {
+
+    pub fn get_start_of_date(timestamp: i64) -> i64 {
+        let seconds_per_day: i64 = 86400;
+        (timestamp / seconds_per_day) * seconds_per_day
+    }
+
+    pub fn get_end_of_date(timestamp: i64) -> i64 {
+        let seconds_per_day: i64 = 86400;
+        (timestamp / seconds_per_day) * seconds_per_day + seconds_per_day - 1
+    }
+
+    pub fn get_diff_days(timestamp1: i64, timestamp2: i64) -> i64 {
+        let seconds_per_day: i64 = 86400;
+        (timestamp1 - timestamp2) / seconds_per_day
+    }
+}
+// Add UpdatePool account validation structure
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(constraint = protocol.owner == authority.key() || protocol.governance == authority.key())]
+    pub authority: Signer<'info>,
+    pub config_registry: Option<Account<'info, ConfigRegistry>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+    #[account(
+        mut,
+        realloc = 8 + Pool::LEN,
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        constraint = reward_vault.mint == reward_mint.key() && reward_vault.owner == protocol.key()
+            @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub config_registry: Option<Account<'info, ConfigRegistry>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(mut, close = authority)]
+    pub pool: Account<'info, Pool>,
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut, close = authority, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    #[account(
+        mut,
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolHistory<'info> {
+    pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = payer,
+        space = PoolHistory::LEN,
+        seeds = [b"pool_history", pool.key().as_ref()],
+        bump
+    )]
+    pub pool_history: Account<'info, PoolHistory>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut)]
+    pub funder_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = protocol_vault.mint == pool.reward_token && protocol_vault.owner == protocol.key()
+            @ ErrorCode::InvalidRewardVault
+    )]
+    pub protocol_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.reward_token @ ErrorCode::InvalidMint)]
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    #[account(
+        mut,
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocolConfig<'info> {
+    #[account(mut, seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub config_registry: Option<Account<'info, ConfigRegistry>>,
+}
+
+#[derive(Accounts)]
+pub struct SetProtocolRole<'info> {
+    #[account(mut, seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetConfigRegistry<'info> {
+    #[account(mut, seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    // Governance only, an owner-made registry could carry any bounds
+    #[account(constraint = protocol.governance == authority.key() @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>,
+    #[account(constraint = governance_state.authority == protocol.governance @ ErrorCode::InvalidConfigRegistry)]
+    pub governance_state: Account<'info, GovernanceState>,
+    #[account(
+        constraint = config_registry.governance == governance_state.key() @ ErrorCode::InvalidConfigRegistry
+    )]
+    pub config_registry: Account<'info, ConfigRegistry>,
 }
 
-// Account validation structures
+// Update Deposit account validation structure with init_if_needed
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(init_if_needed, payer = user, space = UserInfo::LEN)]
+    pub user_info: Account<'info, UserInfo>,
     #[account(
-        init, 
-        payer = owner, 
-        space = ProtocolAccount::LEN,
-        seeds = [b"protocol"],
+        init_if_needed,
+        payer = user,
+        space = WalletPosition::LEN,
+        seeds = [b"wallet_position", pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub wallet_position: Account<'info, WalletPosition>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = Depositor::LEN,
+        seeds = [b"depositor", user.key().as_ref()],
         bump
     )]
+    pub depositor: Account<'info, Depositor>,
+    #[account(mut, seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
-    #[account(init, payer = owner, space = UserInfo::LEN)]
-    pub user_info: Account<'info, UserInfo>,
+    #[account(mut, seeds = [b"protocol_stats"], bump = protocol_stats.bump)]
+    pub protocol_stats: Account<'info, ProtocolStats>,
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Only what lands in the protocol's own account for the deposit mint is credited
+    #[account(
+        mut,
+        constraint = protocol_token_account.mint == pool.deposit_token && protocol_token_account.owner == protocol.key()
+            @ ErrorCode::InvalidProtocolTokenAccount
+    )]
+    pub protocol_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.deposit_token @ ErrorCode::InvalidMint)]
+    pub deposit_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    // Resolves the referrer when depositing with a code instead of a pubkey
+    pub referral_code: Option<Account<'info, ReferralCode>>,
+    // Direct referrer's account, required when the user has a referrer
+    #[account(mut, constraint = referrer_account.pool == pool.key() @ ErrorCode::InvalidReferrerAccount)]
+    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+    // Daily snapshots are only written when the pool's history is passed
+    #[account(mut, seeds = [b"pool_history", pool.key().as_ref()], bump = pool_history.bump)]
+    pub pool_history: Option<Account<'info, PoolHistory>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(minimum_deposit: u64, lock_period: i64, can_swap: bool, rate: u64, apy: u64)]
-pub struct AddPool<'info> {
+#[instruction(code: String)]
+pub struct RegisterReferralCode<'info> {
+    #[account(
+        init,
+        payer = referrer,
+        space = ReferralCode::LEN,
+        seeds = [b"referral_code", code.as_bytes()],
+        bump
+    )]
+    pub referral_code: Account<'info, ReferralCode>,
     #[account(mut)]
-    pub protocol: Account<'info, ProtocolAccount>,
+    pub referrer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
 
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    pub pool: Account<'info, Pool>,
     #[account(
         init,
         payer = payer,
-        space = 8 + Pool::LEN,
-        seeds = [b"pool", protocol.key().as_ref()],
+        space = ReferrerAccount::LEN,
+        seeds = [b"referrer", pool.key().as_ref(), referrer.key().as_ref()],
         bump
     )]
-    pub pool: Account<'info, Pool>,
-
-    pub deposit_token: Account<'info, Mint>,
-    pub reward_token: Account<'info, Mint>,
-
+    pub referrer_account: Account<'info, ReferrerAccount>,
+    /// CHECK: only the key is stored, the referrer doesn't need to sign
+    pub referrer: UncheckedAccount<'info>,
     #[account(mut)]
     pub payer: Signer<'info>,
-
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct ViewUserDeposit<'info> {
+#[instruction(pool_id: u64)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
     #[account(
-        constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority,
+        mut,
+        seeds = [b"referrer", pool.key().as_ref(), referrer.key().as_ref()],
+        bump = referrer_account.bump,
+        has_one = referrer
     )]
-    pub user_info: Account<'info, UserInfo>,
-    pub user: Signer<'info>,
+    pub referrer_account: Account<'info, ReferrerAccount>,
+    #[account(
+        mut,
+        constraint = protocol_vault.mint == pool.reward_token @ ErrorCode::InvalidReferrerAccount
+    )]
+    pub protocol_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = referrer_token_account.mint == pool.reward_token @ ErrorCode::InvalidReferrerAccount
+    )]
+    pub referrer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.reward_token @ ErrorCode::InvalidMint)]
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct ViewUserInfo<'info> {
+pub struct SetReferralConfig<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
     #[account(
-        constraint = user_info.authority == user.key()
+        init_if_needed,
+        payer = authority,
+        space = ReferralConfig::LEN,
+        seeds = [b"referral_config", pool.key().as_ref()],
+        bump
     )]
-    pub user_info: Account<'info, UserInfo>,
-    pub user: Signer<'info>,
+    pub referral_config: Account<'info, ReferralConfig>,
+    #[account(
+        mut,
+        constraint = protocol.owner == authority.key() || protocol.governance == authority.key()
+            @ ErrorCode::NotOwnerOrGovernance
+    )]
+    pub authority: Signer<'info>,
+    pub config_registry: Option<Account<'info, ConfigRegistry>>,
+    pub system_program: Program<'info, System>,
 }
 
+// Default single-level referral percent, used when a pool has no referral config
+pub const REF_PERCENT: u64 = 200; // 2%
 
+// How far ahead a funded pool without an end has to be able to pay its positions
+pub const SOLVENCY_HORIZON: i64 = 365 * 86400;
 
+// Fixed-point scale of Pool::acc_reward_per_share
+pub const REWARD_PER_SHARE_PRECISION: u128 = 1_000_000_000_000;
 
-// Complete date_helper module implementation
-pub mod date_helper {
+// Upper bound on what a full referral chain can take from a claim
+pub const MAX_TOTAL_REF_PERCENT: u64 = 2000; // 20%
 
-    pub fn get_start_of_date(timestamp: i64) -> i64 {
-        let seconds_per_day: i64 = 86400;
-        (timestamp / seconds_per_day) * seconds_per_day
-    }
+// Add helper function implementations
 
-    pub fn get_end_of_date(timestamp: i64) -> i64 {
-        let seconds_per_day: i64 = 86400;
-        (timestamp / seconds_per_day) * seconds_per_day + seconds_per_day - 1
+// Enforce a governance maximum once the protocol is linked to a config registry
+fn check_config_limit(
+    protocol: &ProtocolAccount,
+    config_registry: &Option<Account<ConfigRegistry>>,
+    key: &str,
+    value: u64,
+) -> Result<()> {
+    if protocol.config_registry == Pubkey::default() {
+        return Ok(());
     }
 
-    pub fn get_diff_days(timestamp1: i64, timestamp2: i64) -> i64 {
-        let seconds_per_day: i64 = 86400;
-        (timestamp1 - timestamp2) / seconds_per_day
+    let registry = config_registry.as_ref().ok_or(ErrorCode::ConfigRegistryRequired)?;
+    require_keys_eq!(registry.key(), protocol.config_registry, ErrorCode::InvalidConfigRegistry);
+
+    if let Some(max) = registry.get_u64(key) {
+        require!(value <= max, ErrorCode::ConfigLimitExceeded);
     }
+    Ok(())
 }
-// Add UpdatePool account validation structure
-#[derive(Accounts)]
-pub struct UpdatePool<'info> {
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    #[account(mut)]
-    pub protocol: Account<'info, ProtocolAccount>,
-    #[account(constraint = protocol.owner == authority.key() || protocol.governance == authority.key())]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
+
+fn calculate_reward(_pool_id: u64, user_info: &UserInfo, pool: &Pool) -> Result<u64> {
+    if pool.accounting_mode == AccountingMode::SharedEmissions {
+        return calculate_shared_reward(user_info, pool, Clock::get()?.unix_timestamp);
+    }
+
+    accrue_reward(
+        user_info.amount,
+        user_info.last_claimed,
+        pool.reward_cutoff(Clock::get()?.unix_timestamp),
+        user_info.pending_reward,
+        |timestamp| pool.get_apy(timestamp),
+        |timestamp| pool.get_rate(timestamp),
+        safe_decimals(&pool.deposit_token)?,
+        safe_decimals(&pool.reward_token)?,
+    )
 }
 
-// Update Deposit account validation structure with init_if_needed
-#[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    #[account(init_if_needed, payer = user, space = UserInfo::LEN)]
-    pub user_info: Account<'info, UserInfo>,
-    #[account(mut)]
-    pub protocol: Account<'info, ProtocolAccount>,
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub protocol_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+// Pending reward plus the user's share of everything emitted since their last update
+fn calculate_shared_reward(user_info: &UserInfo, pool: &Pool, timestamp: i64) -> Result<u64> {
+    let accumulated = (user_info.amount as u128)
+        .checked_mul(pool.acc_reward_per_share_at(timestamp)?)
+        .map(|value| value / REWARD_PER_SHARE_PRECISION)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    let earned = accumulated
+        .checked_sub(user_info.reward_debt)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    u64::try_from(earned)
+        .ok()
+        .and_then(|earned| user_info.pending_reward.checked_add(earned))
+        .ok_or(ErrorCode::ArithmeticError.into())
 }
 
-// Add constant for REF_PERCENT
-pub const REF_PERCENT: u64 = 200; // 2%
+// Brings the pending amount of every reward stream up to now. Has to run before the
+// user's amount or last_claimed change, streams only accrue from when they were added.
+// Credits the position's reward up to now, the pool owes what it adds until it's claimed
+fn settle_pending_reward(pool_id: u64, user_info: &mut UserInfo, pool: &mut Pool) -> Result<()> {
+    let pending_reward = calculate_reward(pool_id, user_info, pool)?;
+    pool.rewards_owed = pool.rewards_owed
+        .checked_add(pending_reward.saturating_sub(user_info.pending_reward))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    user_info.pending_reward = pending_reward;
+    Ok(())
+}
 
-// Add helper function implementations
+fn update_stream_rewards(user_info: &mut UserInfo, pool: &Pool) -> Result<()> {
+    let deposit_decimals = safe_decimals(&pool.deposit_token)?;
+    let until = pool.reward_cutoff(Clock::get()?.unix_timestamp);
+    user_info.pending_stream_rewards.resize(pool.reward_streams.len(), 0);
 
-fn calculate_reward(_pool_id: u64, user_info: &UserInfo, pool: &Pool) -> Result<u64> {
-    let amount: u128 = user_info.amount.into();
-    let last_claimed: u64 = user_info.last_claimed;
-    let total_reward: u64 = user_info.pending_reward;
+    for (index, stream) in pool.reward_streams.iter().enumerate() {
+        let since = if user_info.last_claimed == 0 {
+            0
+        } else {
+            user_info.last_claimed.max(stream.start_timestamp as u64)
+        };
+        user_info.pending_stream_rewards[index] = accrue_reward(
+            user_info.amount,
+            since,
+            until,
+            user_info.pending_stream_rewards[index],
+            |timestamp| stream.get_apy(timestamp),
+            |timestamp| stream.get_rate(timestamp),
+            deposit_decimals,
+            // Same basis as the base reward
+            safe_decimals(&stream.mint)?,
+        )?;
+    }
+    Ok(())
+}
 
-    if amount == 0 || last_claimed == 0 {
+// Adds the reward earned by `amount` from `last_claimed` until `until` to `total_reward`,
+// day by day with the APY and rate in effect on each day
+#[allow(clippy::too_many_arguments)]
+fn accrue_reward(
+    amount: u64,
+    last_claimed: u64,
+    until: i64,
+    total_reward: u64,
+    apy_at: impl Fn(i64) -> u64,
+    rate_at: impl Fn(i64) -> u64,
+    deposit_decimals: u8,
+    reward_decimals: u8,
+) -> Result<u64> {
+    let amount: u128 = amount.into();
+
+    let current_time = until.max(0) as u64;
+    if amount == 0 || last_claimed == 0 || current_time <= last_claimed {
         return Ok(total_reward);
     }
 
-    let clock = Clock::get()?;
-    let current_time = clock.unix_timestamp as u64;
     let start_timestamp = date_helper::get_start_of_date(last_claimed as i64) as u64;
     let mut total_time_reward: u64 = 0;
     let mut current_claimed = last_claimed;
@@ -1098,8 +3035,8 @@ fn calculate_reward(_pool_id: u64, user_info: &UserInfo, pool: &Pool) -> Result<
             end_day - current_claimed
         };
 
-        let apy = pool.get_apy(timestamp as i64);
-        let rate = pool.get_rate(timestamp as i64);
+        let apy = apy_at(timestamp as i64);
+        let rate = rate_at(timestamp as i64);
 
         // Calculate yield amount with proper error handling
         let yield_amount = match amount.checked_mul(applicable_timestamp as u128) {
@@ -1137,23 +3074,7 @@ fn calculate_reward(_pool_id: u64, user_info: &UserInfo, pool: &Pool) -> Result<
         timestamp += seconds_per_day;
     }
 
-    // Adjust for token decimal differences (if needed) - similar to Solidity implementation
-    let deposit_decimals = safe_decimals(&pool.deposit_token)?;
-    let reward_decimals = safe_decimals(&pool.reward_token)?;
-    
-    let adjusted_reward = if reward_decimals >= deposit_decimals {
-        let multiplier = 10_u64.pow((reward_decimals - deposit_decimals) as u32);
-        match total_time_reward.checked_mul(multiplier) {
-            Some(result) => result,
-            None => return err!(ErrorCode::ArithmeticError)
-        }
-    } else {
-        let divisor = 10_u64.pow((deposit_decimals - reward_decimals) as u32);
-        match total_time_reward.checked_div(divisor) {
-            Some(result) => result,
-            None => return err!(ErrorCode::ArithmeticError)
-        }
-    };
+    let adjusted_reward = adjust_decimals(total_time_reward, deposit_decimals, reward_decimals)?;
 
     // Add final result with proper error handling
     match total_reward.checked_add(adjusted_reward) {
@@ -1162,6 +3083,23 @@ fn calculate_reward(_pool_id: u64, user_info: &UserInfo, pool: &Pool) -> Result<
     }
 }
 
+// Adjust for token decimal differences (if needed) - similar to Solidity implementation
+fn adjust_decimals(amount: u64, deposit_decimals: u8, reward_decimals: u8) -> Result<u64> {
+    if reward_decimals >= deposit_decimals {
+        let multiplier = 10_u64.pow((reward_decimals - deposit_decimals) as u32);
+        match amount.checked_mul(multiplier) {
+            Some(result) => Ok(result),
+            None => err!(ErrorCode::ArithmeticError)
+        }
+    } else {
+        let divisor = 10_u64.pow((deposit_decimals - reward_decimals) as u32);
+        match amount.checked_div(divisor) {
+            Some(result) => Ok(result),
+            None => err!(ErrorCode::ArithmeticError)
+        }
+    }
+}
+
 
 fn calculate_swap(pool: &Pool, amount: u64, direction: bool) -> Result<u64> {
     let timestamp = Clock::get()?.unix_timestamp;
@@ -1207,6 +3145,25 @@ fn calculate_sum_available_for_withdraw(user_info: &UserInfo) -> Result<u64> {
     Ok(sum)
 }
 
+// Takes `amount` out of the unlocked deposits, oldest first
+fn take_unlocked_deposits(user_info: &mut UserInfo, amount: u64, timestamp: i64) -> Result<()> {
+    let mut remaining = amount;
+    for deposit in &mut user_info.deposits {
+        if remaining == 0 {
+            break;
+        }
+        if deposit.is_withdrawn || deposit.locked_until > timestamp {
+            continue;
+        }
+        let taken = deposit.amount.min(remaining);
+        deposit.amount -= taken;
+        deposit.is_withdrawn = deposit.amount == 0;
+        remaining -= taken;
+    }
+    require!(remaining == 0, ErrorCode::InsufficientAmount);
+    Ok(())
+}
+
 fn mark_deposits_as_withdrawn(user_info: &mut UserInfo) -> Result<()> {
     let clock = Clock::get()?;
     for deposit in &mut user_info.deposits {
@@ -1235,19 +3192,32 @@ pub(crate) fn safe_decimals(token: &Pubkey) -> Result<u8> {
 pub struct Swap<'info> {
     #[account(mut)]
     pub pool: Account<'info, Pool>,
-    #[account(mut)]
+    #[account(mut, seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
-    #[account(mut)]
-    pub user_input_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub protocol_input_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub protocol_output_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_output_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = user_input_account.mint == input_mint.key() @ ErrorCode::InvalidMint)]
+    pub user_input_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = protocol_input_account.mint == input_mint.key() && protocol_input_account.owner == protocol.key()
+            @ ErrorCode::InvalidProtocolTokenAccount
+    )]
+    pub protocol_input_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = protocol_output_account.mint == output_mint.key() && protocol_output_account.owner == protocol.key()
+            @ ErrorCode::InvalidProtocolTokenAccount
+    )]
+    pub protocol_output_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_output_account.mint == output_mint.key() @ ErrorCode::InvalidMint)]
+    pub user_output_account: InterfaceAccount<'info, TokenAccount>,
+    pub input_mint: InterfaceAccount<'info, Mint>,
+    pub output_mint: InterfaceAccount<'info, Mint>,
+    // Daily snapshots are only written when the pool's history is passed
+    #[account(mut, seeds = [b"pool_history", pool.key().as_ref()], bump = pool_history.bump)]
+    pub pool_history: Option<Account<'info, PoolHistory>>,
     #[account(mut)]
     pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 
@@ -1260,6 +3230,7 @@ pub struct ViewState<'info> {
 pub struct ViewUserPoolInfo<'info> {
     pub protocol: Account<'info, ProtocolAccount>,
     pub user_info: Account<'info, UserInfo>,
+    #[account(constraint = user_info.pool == pool.key() @ ErrorCode::InvalidUserInfoPool)]
     pub pool: Account<'info, Pool>,
 }
 
@@ -1273,20 +3244,28 @@ pub struct ViewPool<'info> {
 #[derive(Accounts)]
 #[instruction(pool_id: u64)]
 pub struct Claim<'info> {
+    #[account(mut, constraint = user_info.pool == pool.key() @ ErrorCode::InvalidUserInfoPool)]
     pub pool: Account<'info, Pool>,
     #[account(mut, seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
-    #[account(mut)]
+    #[account(mut, constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority)]
     pub user_info: Account<'info, UserInfo>,
     #[account(mut)]
-    pub protocol_vault: Account<'info, TokenAccount>,
+    pub protocol_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(seeds = [b"referral_config", pool.key().as_ref()], bump = referral_config.bump)]
+    pub referral_config: Option<Account<'info, ReferralConfig>>,
     #[account(mut)]
-    pub referrer_vault: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.reward_token @ ErrorCode::InvalidMint)]
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    // Daily snapshots are only written when the pool's history is passed
+    #[account(mut, seeds = [b"pool_history", pool.key().as_ref()], bump = pool_history.bump)]
+    pub pool_history: Option<Account<'info, PoolHistory>>,
     #[account(mut)]
     pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -1304,11 +3283,62 @@ pub struct Masscall<'info> {
     pub authority: Signer<'info>,
     // Validates governance program ID
     #[account(
-        constraint = governanceProgram.key() == Pubkey::from_str("Governance111111111111111111111111111111111").unwrap() 
+        constraint = governance_program.key() == governance::ID
         @ ErrorCode::InvalidProgramId
     )]
     /// CHECK: Just used for program ID validation
-    pub governanceProgram: UncheckedAccount<'info>,
+    pub governance_program: UncheckedAccount<'info>,
+    #[account(seeds = [b"masscall_allowlist"], bump)]
+    pub masscall_allowlist: Account<'info, MasscallAllowlist>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMasscallAllowlist<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = MasscallAllowlist::LEN,
+        seeds = [b"masscall_allowlist"],
+        bump
+    )]
+    pub masscall_allowlist: Account<'info, MasscallAllowlist>,
+    #[account(
+        mut,
+        constraint = authority.key() == protocol.governance @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMasscallAllowlist<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut, seeds = [b"masscall_allowlist"], bump)]
+    pub masscall_allowlist: Account<'info, MasscallAllowlist>,
+    // Only governance can widen or narrow what masscall may reach
+    #[account(constraint = authority.key() == protocol.governance @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct SetOutflowLimit<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = OutflowLimit::LEN,
+        seeds = [b"outflow_limit", mint.as_ref()],
+        bump
+    )]
+    pub outflow_limit: Account<'info, OutflowLimit>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
@@ -1316,19 +3346,74 @@ pub struct Masscall<'info> {
 pub struct Withdraw<'info> {
     #[account(mut, seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
-    #[account(mut)]
+    #[account(mut, seeds = [b"protocol_stats"], bump = protocol_stats.bump)]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(mut, constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority)]
     pub user_info: Account<'info, UserInfo>,
     #[account(mut)]
     pub user: Signer<'info>,
+    #[account(mut, constraint = user_info.pool == pool.key() @ ErrorCode::InvalidUserInfoPool)]
     pub pool: Account<'info, Pool>,
     #[account(mut)]
-    pub protocol_token_account: Account<'info, TokenAccount>,
+    pub protocol_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
     #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.deposit_token @ ErrorCode::InvalidMint)]
+    pub deposit_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    // Daily snapshots are only written when the pool's history is passed
+    #[account(mut, seeds = [b"pool_history", pool.key().as_ref()], bump = pool_history.bump)]
+    pub pool_history: Option<Account<'info, PoolHistory>>,
+    // Required when the user has a referrer
+    #[account(mut, constraint = referrer_account.pool == pool.key() @ ErrorCode::InvalidReferrerAccount)]
+    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+    #[account(mut, seeds = [b"wallet_position", pool.key().as_ref(), user.key().as_ref()], bump = wallet_position.bump)]
+    pub wallet_position: Option<Account<'info, WalletPosition>>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>
 }
 
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut, seeds = [b"protocol_stats"], bump = protocol_stats.bump)]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(mut, constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority)]
+    pub user_info: Account<'info, UserInfo>,
+    #[account(mut, constraint = user_info.pool == pool.key() @ ErrorCode::InvalidUserInfoPool)]
+    pub pool: Account<'info, Pool>,
+    pub user: Signer<'info>,
+    // Required when the user has a referrer
+    #[account(mut, constraint = referrer_account.pool == pool.key() @ ErrorCode::InvalidReferrerAccount)]
+    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+    #[account(mut, seeds = [b"wallet_position", pool.key().as_ref(), user.key().as_ref()], bump = wallet_position.bump)]
+    pub wallet_position: Option<Account<'info, WalletPosition>>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(seeds = [b"protocol"], bump)]
+    pub protocol: Account<'info, ProtocolAccount>,
+    #[account(mut, constraint = user_info.authority == user.key() @ ErrorCode::InvalidAuthority)]
+    pub user_info: Account<'info, UserInfo>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, constraint = user_info.pool == pool.key() @ ErrorCode::InvalidUserInfoPool)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub protocol_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = pool.deposit_token @ ErrorCode::InvalidMint)]
+    pub deposit_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, seeds = [b"sol_vault", pool.key().as_ref()], bump = sol_vault.bump)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct Approve<'info> {
     #[account(mut)]
@@ -1367,8 +3452,8 @@ pub struct SendFromPool<'info> {
     #[account(mut)]
     pub protocol: Account<'info, ProtocolAccount>,
     #[account(mut)]
-    pub protocol_vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub protocol_vault: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -1379,10 +3464,10 @@ pub struct SafeSendFromPool<'info> {
     #[account(mut, seeds = [b"protocol"], bump)]
     pub protocol: Account<'info, ProtocolAccount>,
     #[account(mut)]
-    pub protocol_reward_account: Account<'info, TokenAccount>,
+    pub protocol_reward_account: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
-    pub recipient_account: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub recipient_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 impl<'info> SafeSendFromPool<'info> {
@@ -1439,48 +3524,92 @@ impl<'info> SafeSendFromPool<'info> {
     }
 }
 
-#[derive(Accounts)]
-#[instruction(pool_id: u64)]
-pub struct ProcessRefReward<'info> {
-    pub pool: Account<'info, Pool>,
-    #[account(mut, seeds = [b"protocol"], bump)]
-    pub protocol: Account<'info, ProtocolAccount>,
-    pub user_info: Account<'info, UserInfo>,
-    #[account(mut)]
-    pub protocol_vault: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub referrer_vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+// Stake leaving a referred position stops counting towards the direct referrer's tier
+fn release_referred_stake(
+    user_info: &UserInfo,
+    referrer_account: Option<&mut Account<ReferrerAccount>>,
+    amount: u64,
+) -> Result<()> {
+    if user_info.referrer == Pubkey::default() {
+        return Ok(());
+    }
+    let referrer_account = referrer_account.ok_or(ErrorCode::ReferrerAccountRequired)?;
+    require_keys_eq!(referrer_account.referrer, user_info.referrer, ErrorCode::InvalidReferrerAccount);
+    referrer_account.release_referral(amount);
+    Ok(())
 }
 
-// Updated process_ref_reward implementation:
+// Accrues each referrer up the user's chain their share of `reward` and returns the total.
+// Referrer accounts are looked up among the remaining accounts, one is required for every
+// linked referrer.
 pub fn process_ref_reward<'info>(
-    ctx: &Context<Claim<'info>>,
-    _pool_id: u64,
-    ref_amount: u64,
-    referrer: Pubkey,
-    protocol_bump: u8,
-) -> Result<()> {
-    if referrer != Pubkey::default() {
-        // Ensure the referrer_vault belongs to the referrer by checking the owner
-        // This is a simplified check - in a real implementation, you'd want to verify the account
-        // This is a simplified check - in a real implementation, you'd want to verify the account
-        let seeds = &[b"protocol" as &[u8], &[protocol_bump]];
-        let signer = &[&seeds[..]];
+    ctx: &Context<'_, '_, '_, 'info, Claim<'info>>,
+    pool_id: u64,
+    reward: u64,
+) -> Result<u64> {
+    // Once a pool has its own config the protocol default no longer applies to it
+    require!(
+        !ctx.accounts.pool.has_referral_config || ctx.accounts.referral_config.is_some(),
+        ErrorCode::ReferralConfigRequired
+    );
+    let default_levels = [ctx.accounts.protocol.ref_percent];
+    let levels = match ctx.accounts.referral_config.as_ref() {
+        Some(referral_config) => referral_config.level_percents.as_slice(),
+        None => &default_levels[..],
+    };
+    let pool_key = ctx.accounts.pool.key();
 
-        let transfer_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                authority: ctx.accounts.protocol.to_account_info(),
-                from: ctx.accounts.protocol_vault.to_account_info(),
-                to: ctx.accounts.referrer_vault.to_account_info(),
-            },
-            signer,
+    // Shares of an ineligible referee are never accrued and stay in the reward vault
+    let now = Clock::get()?.unix_timestamp;
+    if !ctx.accounts.protocol.is_referral_eligible(&ctx.accounts.user_info, now) {
+        msg!("Referee is not yet eligible, referral rewards stay in the vault");
+        return Ok(0);
+    }
+
+    let mut referee = ctx.accounts.user.key();
+    let mut total_accrued: u64 = 0;
+    for (level, level_percent) in levels.iter().enumerate() {
+        let referrer = match ctx.accounts.protocol.get_referrer(&referee) {
+            Some(referrer) => referrer,
+            None => break,
+        };
+
+        let (referrer_key, _) = Pubkey::find_program_address(
+            &[b"referrer", pool_key.as_ref(), referrer.as_ref()],
+            &crate::ID,
         );
-        token::transfer(transfer_ctx, ref_amount)?;
+        // Every linked referrer has to be passed, a share is never dropped silently
+        let referrer_info = ctx.remaining_accounts
+            .iter()
+            .find(|account_info| account_info.key == &referrer_key && account_info.is_writable)
+            .ok_or(ErrorCode::ReferrerAccountRequired)?;
+        require_keys_eq!(*referrer_info.owner, crate::ID, ErrorCode::InvalidReferrerAccount);
+        let mut data = referrer_info.try_borrow_mut_data()?;
+        let mut referrer_account = ReferrerAccount::try_deserialize(&mut &data[..])?;
+
+        let percent = match ctx.accounts.referral_config.as_ref() {
+            Some(referral_config) => referral_config.percent_for(level, referrer_account.total_referred_stake),
+            None => *level_percent,
+        };
+        let ref_amount = reward
+            .checked_mul(percent)
+            .and_then(|value| value.checked_div(10000))
+            .ok_or(ErrorCode::ArithmeticError)?;
+
+        referrer_account.accrue(ref_amount)?;
+        referrer_account.try_serialize(&mut &mut data[..])?;
+        total_accrued = total_accrued.checked_add(ref_amount).ok_or(ErrorCode::ArithmeticError)?;
+
+        emit!(ReferralRewardEvent {
+            referrer,
+            referee,
+            pool_id,
+            level: level as u8,
+            amount: ref_amount,
+        });
+        referee = referrer;
     }
-    Ok(())
+    Ok(total_accrued)
 }
 
 // Test helper functions - only for testing purposes
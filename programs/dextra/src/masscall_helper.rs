@@ -0,0 +1,295 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_spl::token::spl_token::instruction::TokenInstruction;
+use anchor_spl::{token, token_2022};
+
+use anchor_lang::solana_program::program::{get_return_data, invoke, invoke_signed};
+
+use crate::{
+    AccountRange, ErrorCode, MasscallAllowlist, MasscallExecuted, MasscallReport, MasscallResult,
+    OutflowLimit, OutflowReport, ProtocolOutflowEvent, TokenCall,
+};
+
+// Collects the accounts an entry refers to, in range order
+pub fn select_accounts<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    ranges: &[AccountRange],
+) -> Result<Vec<AccountInfo<'info>>> {
+    let mut accounts = Vec::new();
+    for range in ranges {
+        let start = range.start as usize;
+        let end = range.end as usize;
+        require!(start < end && end <= remaining_accounts.len(), ErrorCode::InvalidAccountRange);
+        accounts.extend_from_slice(&remaining_accounts[start..end]);
+    }
+    Ok(accounts)
+}
+
+// Converts accounts to metas, only the authority may sign and the protocol PDA signs on request
+pub fn build_account_metas(
+    accounts: &[AccountInfo],
+    protocol: &Pubkey,
+    authority: &Pubkey,
+    sign_with_protocol: bool,
+) -> Result<Vec<AccountMeta>> {
+    let mut account_metas = Vec::with_capacity(accounts.len());
+    for account_info in accounts {
+        let is_protocol = account_info.key == protocol;
+        if account_info.is_signer && account_info.key != authority {
+            msg!("Unauthorized signer detected: {}", account_info.key);
+            return Err(ErrorCode::UnauthorizedSigner.into());
+        }
+
+        let is_signer = account_info.is_signer || (is_protocol && sign_with_protocol);
+        let meta = if account_info.is_writable {
+            AccountMeta::new(*account_info.key, is_signer)
+        } else {
+            AccountMeta::new_readonly(*account_info.key, is_signer)
+        };
+        account_metas.push(meta);
+    }
+    Ok(account_metas)
+}
+
+impl TokenCall {
+    // Decodes the instruction data, anything outside the supported set is rejected
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let instruction = TokenInstruction::unpack(data)
+            .map_err(|_| error!(ErrorCode::UnsupportedTokenInstruction))?;
+        let call = match instruction {
+            TokenInstruction::Transfer { amount } => TokenCall::Transfer { amount },
+            TokenInstruction::TransferChecked { amount, decimals } => {
+                TokenCall::TransferChecked { amount, decimals }
+            }
+            TokenInstruction::Approve { amount } => TokenCall::Approve { amount },
+            TokenInstruction::Revoke => TokenCall::Revoke,
+            TokenInstruction::CloseAccount => TokenCall::CloseAccount,
+            TokenInstruction::SyncNative => TokenCall::SyncNative,
+            _ => return err!(ErrorCode::UnsupportedTokenInstruction),
+        };
+        Ok(call)
+    }
+
+    // Accounts the instruction takes with a single owner, multisig owners are not supported
+    pub fn account_count(&self) -> usize {
+        match self {
+            TokenCall::Transfer { .. } => 3,        // source, destination, owner
+            TokenCall::TransferChecked { .. } => 4, // source, mint, destination, owner
+            TokenCall::Approve { .. } => 3,         // source, delegate, owner
+            TokenCall::Revoke => 2,                 // source, owner
+            TokenCall::CloseAccount => 3,           // account, destination, owner
+            TokenCall::SyncNative => 1,             // account
+        }
+    }
+
+    // Tokens that leave the owner's control, approvals count since the delegate can spend them
+    pub fn outflow_amount(&self) -> Option<u64> {
+        match self {
+            TokenCall::Transfer { amount }
+            | TokenCall::TransferChecked { amount, .. }
+            | TokenCall::Approve { amount } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    // Position of the account the tokens go to, if any
+    fn destination_index(&self) -> Option<usize> {
+        match self {
+            TokenCall::Transfer { .. } | TokenCall::Approve { .. } => Some(1),
+            TokenCall::TransferChecked { .. } => Some(2),
+            _ => None,
+        }
+    }
+
+    // Position of the authority that has to sign, if the instruction has one
+    pub fn owner_index(&self) -> Option<usize> {
+        match self {
+            TokenCall::SyncNative => None,
+            _ => Some(self.account_count() - 1),
+        }
+    }
+}
+
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == token::ID || *program_id == token_2022::ID
+}
+
+// Builds metas for a token instruction, returns whether the protocol PDA has to sign as owner.
+// Accounts past the instruction's own (token program, outflow limit) are not part of the instruction.
+pub fn build_token_account_metas(
+    call: &TokenCall,
+    accounts: &[AccountInfo],
+    protocol: &Pubkey,
+) -> Result<(Vec<AccountMeta>, bool)> {
+    require!(
+        accounts.len() >= call.account_count(),
+        ErrorCode::InvalidTokenInstructionAccounts
+    );
+    let accounts = &accounts[..call.account_count()];
+
+    let owner_index = call.owner_index();
+    let sign_with_protocol = match owner_index {
+        Some(index) => {
+            let owner = &accounts[index];
+            if owner.key == protocol {
+                true
+            } else if owner.is_signer {
+                false
+            } else {
+                msg!("Token owner {} is not the protocol and not a signer", owner.key);
+                return Err(ErrorCode::UnauthorizedSigner.into());
+            }
+        }
+        None => false,
+    };
+
+    let mut account_metas = Vec::with_capacity(accounts.len());
+    for (i, account_info) in accounts.iter().enumerate() {
+        let is_owner = owner_index == Some(i);
+        if account_info.is_signer && !is_owner {
+            msg!("Unauthorized signer detected: {}", account_info.key);
+            return Err(ErrorCode::UnauthorizedSigner.into());
+        }
+
+        let is_signer = is_owner && (account_info.is_signer || sign_with_protocol);
+        let meta = if account_info.is_writable {
+            AccountMeta::new(*account_info.key, is_signer)
+        } else {
+            AccountMeta::new_readonly(*account_info.key, is_signer)
+        };
+        account_metas.push(meta);
+    }
+    Ok((account_metas, sign_with_protocol))
+}
+
+// Checks a protocol-signed outflow against the OutflowLimit PDA of the source mint and,
+// unless `dry_run`, charges it. The limit account is looked up among `remaining_accounts`,
+// without one the outflow is refused.
+pub fn record_protocol_outflow(
+    call: &TokenCall,
+    accounts: &[AccountInfo],
+    remaining_accounts: &[AccountInfo],
+    dry_run: bool,
+) -> Result<Option<OutflowReport>> {
+    let (amount, destination_index) = match (call.outflow_amount(), call.destination_index()) {
+        (Some(amount), Some(destination_index)) => (amount, destination_index),
+        _ => return Ok(None),
+    };
+
+    let source = &accounts[0];
+    let destination = &accounts[destination_index];
+    let mint = token::accessor::mint(source)?;
+
+    let (limit_key, _) = Pubkey::find_program_address(&[b"outflow_limit", mint.as_ref()], &crate::ID);
+    let limit_info = remaining_accounts
+        .iter()
+        .find(|account_info| account_info.key == &limit_key && account_info.is_writable)
+        .ok_or(ErrorCode::OutflowLimitRequired)?;
+    require_keys_eq!(*limit_info.owner, crate::ID, ErrorCode::OutflowLimitRequired);
+
+    let mut data = limit_info.try_borrow_mut_data()?;
+    let mut outflow_limit = OutflowLimit::try_deserialize(&mut &data[..])?;
+    outflow_limit.consume(amount, Clock::get()?.unix_timestamp)?;
+
+    let report = OutflowReport {
+        mint,
+        amount,
+        spent_in_period: outflow_limit.spent_in_period,
+        amount_per_period: outflow_limit.amount_per_period,
+    };
+    if dry_run {
+        return Ok(Some(report));
+    }
+
+    outflow_limit.try_serialize(&mut &mut data[..])?;
+    emit!(ProtocolOutflowEvent {
+        mint,
+        source: source.key(),
+        destination: destination.key(),
+        amount,
+        spent_in_period: outflow_limit.spent_in_period,
+    });
+    Ok(Some(report))
+}
+
+// A checked call, ready to be invoked
+pub struct PreparedCall {
+    pub instruction: Instruction,
+    pub report: MasscallReport,
+}
+
+// Runs every masscall check for one call: allowlist, signer policy, token semantics and
+// outflow limits. `sign_with_protocol` is only a request for non-token programs, token
+// instructions sign with the protocol exactly when it is the owner.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_call(
+    allowlist: &MasscallAllowlist,
+    program_id: Pubkey,
+    data: Vec<u8>,
+    accounts: &[AccountInfo],
+    remaining_accounts: &[AccountInfo],
+    protocol: &Pubkey,
+    authority: &Pubkey,
+    sign_with_protocol: bool,
+    dry_run: bool,
+) -> Result<PreparedCall> {
+    require!(allowlist.is_allowed(&program_id, &data), ErrorCode::MasscallTargetNotAllowed);
+
+    let (account_metas, sign_with_protocol, token_call, outflow) = if is_token_program(&program_id) {
+        let call = TokenCall::decode(&data)?;
+        let (account_metas, sign_with_protocol) = build_token_account_metas(&call, accounts, protocol)?;
+        let outflow = if sign_with_protocol {
+            record_protocol_outflow(&call, accounts, remaining_accounts, dry_run)?
+        } else {
+            None
+        };
+        msg!("Token instruction {:?} via program {}", call, program_id);
+        (account_metas, sign_with_protocol, Some(call), outflow)
+    } else {
+        let account_metas = build_account_metas(accounts, protocol, authority, sign_with_protocol)?;
+        (account_metas, sign_with_protocol, None, None)
+    };
+
+    let report = MasscallReport {
+        program_id,
+        sign_with_protocol,
+        account_count: account_metas.len() as u32,
+        token_call,
+        outflow,
+    };
+    let instruction = Instruction {
+        program_id,
+        accounts: account_metas,
+        data,
+    };
+    Ok(PreparedCall { instruction, report })
+}
+
+// Invokes a prepared call and collects what the target returned
+pub fn invoke_prepared(
+    prepared: &PreparedCall,
+    call_index: u8,
+    remaining_accounts: &[AccountInfo],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<MasscallResult> {
+    let program_id = prepared.instruction.program_id;
+    if prepared.report.sign_with_protocol {
+        invoke_signed(&prepared.instruction, remaining_accounts, signer_seeds)?;
+    } else {
+        invoke(&prepared.instruction, remaining_accounts)?;
+    }
+
+    // Return data is only the target's if the target set it
+    let return_data = match get_return_data() {
+        Some((returned_by, data)) if returned_by == program_id => data,
+        _ => Vec::new(),
+    };
+
+    emit!(MasscallExecuted {
+        program_id,
+        call_index,
+        sign_with_protocol: prepared.report.sign_with_protocol,
+        return_data: return_data.clone(),
+    });
+    Ok(MasscallResult { program_id, return_data })
+}
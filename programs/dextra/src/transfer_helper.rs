@@ -1,4 +1,13 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Transfer};
+use anchor_spl::token_2022::{
+    self,
+    spl_token_2022::{
+        self,
+        extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    },
+};
+use anchor_spl::token_interface::{self, Mint, TransferChecked};
 
 pub fn safe_transfer_sol(
     to: &AccountInfo,
@@ -16,4 +25,149 @@ pub fn safe_transfer_sol(
         .ok_or(ProgramError::ArithmeticOverflow)?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+// Native SOL from a signer into a program-owned vault
+pub fn transfer_sol_in<'info>(
+    system_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        ),
+        amount,
+    )
+}
+
+// Native SOL out of a program-owned vault, the vault never drops below its rent-exempt minimum
+pub fn transfer_sol_out(vault: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    let minimum_balance = Rent::get()?.minimum_balance(vault.data_len());
+    let available = vault.lamports().saturating_sub(minimum_balance);
+    require!(amount <= available, crate::ErrorCode::InsufficientVaultBalance);
+    safe_transfer_sol(to, vault, amount)
+}
+
+// SPL token transfer where the owner of `from` signs the transaction
+pub fn transfer_tokens<'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    token::transfer(
+        CpiContext::new(
+            token_program.clone(),
+            Transfer {
+                from: from.clone(),
+                to: to.clone(),
+                authority: authority.clone(),
+            },
+        ),
+        amount,
+    )
+}
+
+// SPL token transfer out of an account owned by a program PDA
+pub fn transfer_tokens_signed<'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            Transfer {
+                from: from.clone(),
+                to: to.clone(),
+                authority: authority.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+    )
+}
+
+// Checked transfer where the owner of `from` signs the transaction, for either token program
+pub fn transfer_tokens_checked<'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    token_interface::transfer_checked(
+        CpiContext::new(
+            token_program.clone(),
+            TransferChecked {
+                from: from.clone(),
+                mint: mint.to_account_info(),
+                to: to.clone(),
+                authority: authority.clone(),
+            },
+        ),
+        amount,
+        mint.decimals,
+    )
+}
+
+// Checked transfer out of an account owned by a program PDA, for either token program
+pub fn transfer_tokens_checked_signed<'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: from.clone(),
+                mint: mint.to_account_info(),
+                to: to.clone(),
+                authority: authority.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        mint.decimals,
+    )
+}
+
+// Token-2022 mints may only carry extensions that can't move, freeze or block pool funds.
+// Transfer fees are accounted for on deposit, interest-bearing mints only change the UI amount.
+pub fn ensure_supported_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != token_2022::ID {
+        return Ok(());
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::InterestBearingConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata => {}
+            _ => {
+                msg!("Unsupported mint extension {:?}", extension);
+                return err!(crate::ErrorCode::UnsupportedMintExtension);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,584 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount};
+use dextra::transfer_helper;
+
+declare_id!("FreeLance1111111111111111111111111111111111");
+
+pub const MAX_MILESTONES: usize = 10;
+
+#[program]
+pub mod freelance {
+    use super::*;
+
+    // Pins the governance multisig whose signer PDA arbitrates every escrow, set once after deployment
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.multisig = ctx.accounts.multisig.key();
+        config.bump = ctx.bumps.config;
+        Ok(())
+    }
+
+    // Hands arbitration to another multisig, signed by the current one through `execute_transaction`
+    pub fn set_arbiter_multisig(ctx: Context<SetArbiterMultisig>) -> Result<()> {
+        ctx.accounts.config.multisig = ctx.accounts.new_multisig.key();
+        Ok(())
+    }
+
+    // Client opens an escrow and funds every milestone up front
+    pub fn create_escrow(
+        ctx: Context<CreateEscrow>,
+        escrow_id: u64,
+        contractor: Pubkey,
+        milestones: Vec<MilestoneInput>,
+    ) -> Result<()> {
+        require!(!milestones.is_empty(), FreelanceError::InvalidMilestones);
+        require!(milestones.len() <= MAX_MILESTONES, FreelanceError::InvalidMilestones);
+        require!(contractor != ctx.accounts.client.key(), FreelanceError::InvalidContractor);
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut total: u64 = 0;
+        for milestone in &milestones {
+            require!(milestone.amount > 0, FreelanceError::InvalidMilestones);
+            require!(milestone.deadline > now, FreelanceError::InvalidMilestones);
+            total = total
+                .checked_add(milestone.amount)
+                .ok_or(FreelanceError::ArithmeticError)?;
+        }
+
+        // Disputes are settled by the signer PDA of the configured governance multisig, which
+        // signs `resolve_dispute` through an executed multisig transaction
+        let arbiter = multisig_signer(&ctx.accounts.multisig.key());
+
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.client = ctx.accounts.client.key();
+        escrow.contractor = contractor;
+        escrow.arbiter = arbiter;
+        escrow.mint = ctx.accounts.mint.key();
+        escrow.escrow_id = escrow_id;
+        escrow.total_amount = total;
+        escrow.released_amount = 0;
+        escrow.milestones = milestones
+            .into_iter()
+            .map(|milestone| Milestone {
+                amount: milestone.amount,
+                deadline: milestone.deadline,
+                status: MilestoneStatus::Pending,
+            })
+            .collect();
+        escrow.bump = ctx.bumps.escrow;
+
+        transfer_helper::transfer_tokens(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.client_token_account.to_account_info(),
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.client.to_account_info(),
+            total,
+        )?;
+
+        emit!(EscrowCreatedEvent {
+            escrow: escrow.key(),
+            client: escrow.client,
+            contractor,
+            mint: escrow.mint,
+            total_amount: total,
+        });
+
+        Ok(())
+    }
+
+    // Client accepts the work and releases the milestone to the contractor
+    pub fn approve_milestone(ctx: Context<ApproveMilestone>, index: u8) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow;
+        let milestone = escrow.milestone_mut(index)?;
+        require!(
+            milestone.status == MilestoneStatus::Pending || milestone.status == MilestoneStatus::Disputed,
+            FreelanceError::InvalidMilestoneStatus
+        );
+        milestone.status = MilestoneStatus::Released;
+        let amount = milestone.amount;
+        escrow.record_release(amount)?;
+
+        release_from_vault(
+            escrow,
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.contractor_token_account,
+            amount,
+        )?;
+
+        emit!(MilestoneSettledEvent {
+            escrow: escrow.key(),
+            index,
+            to_contractor: amount,
+            to_client: 0,
+        });
+
+        Ok(())
+    }
+
+    // Either party freezes a pending milestone until the arbiter resolves it
+    pub fn dispute_milestone(ctx: Context<DisputeMilestone>, index: u8) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow;
+        let party = ctx.accounts.party.key();
+        require!(
+            party == escrow.client || party == escrow.contractor,
+            FreelanceError::Unauthorized
+        );
+
+        let milestone = escrow.milestone_mut(index)?;
+        require!(milestone.status == MilestoneStatus::Pending, FreelanceError::InvalidMilestoneStatus);
+        milestone.status = MilestoneStatus::Disputed;
+
+        emit!(MilestoneDisputedEvent {
+            escrow: escrow.key(),
+            index,
+            raised_by: party,
+        });
+
+        Ok(())
+    }
+
+    // Arbiter splits a disputed milestone between contractor and client
+    pub fn resolve_dispute(ctx: Context<ResolveDispute>, index: u8, contractor_amount: u64) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow;
+        let milestone = escrow.milestone_mut(index)?;
+        require!(milestone.status == MilestoneStatus::Disputed, FreelanceError::InvalidMilestoneStatus);
+        require!(contractor_amount <= milestone.amount, FreelanceError::InvalidAmount);
+        milestone.status = MilestoneStatus::Resolved;
+        let amount = milestone.amount;
+        let client_amount = amount - contractor_amount;
+        escrow.record_release(amount)?;
+
+        release_from_vault(
+            escrow,
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.contractor_token_account,
+            contractor_amount,
+        )?;
+        release_from_vault(
+            escrow,
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.client_token_account,
+            client_amount,
+        )?;
+
+        emit!(MilestoneSettledEvent {
+            escrow: escrow.key(),
+            index,
+            to_contractor: contractor_amount,
+            to_client: client_amount,
+        });
+
+        Ok(())
+    }
+
+    // Client takes back a milestone that was neither delivered nor disputed by its deadline
+    pub fn refund_milestone(ctx: Context<RefundMilestone>, index: u8) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let escrow = &mut ctx.accounts.escrow;
+        let milestone = escrow.milestone_mut(index)?;
+        require!(milestone.status == MilestoneStatus::Pending, FreelanceError::InvalidMilestoneStatus);
+        require!(now > milestone.deadline, FreelanceError::DeadlineNotReached);
+        milestone.status = MilestoneStatus::Refunded;
+        let amount = milestone.amount;
+        escrow.record_release(amount)?;
+
+        release_from_vault(
+            escrow,
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.client_token_account,
+            amount,
+        )?;
+
+        emit!(MilestoneSettledEvent {
+            escrow: escrow.key(),
+            index,
+            to_contractor: 0,
+            to_client: amount,
+        });
+
+        Ok(())
+    }
+
+    // Once every milestone is settled the vault and escrow rent go back to the client
+    pub fn close_escrow(ctx: Context<CloseEscrow>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow;
+        require!(
+            escrow.milestones.iter().all(|milestone| milestone.is_settled()),
+            FreelanceError::EscrowNotSettled
+        );
+
+        let client_key = escrow.client;
+        let id_bytes = escrow.escrow_id.to_le_bytes();
+        let seeds = &[b"escrow", client_key.as_ref(), id_bytes.as_ref(), &[escrow.bump]];
+        let signer = &[&seeds[..]];
+
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.vault.to_account_info(),
+                destination: ctx.accounts.client.to_account_info(),
+                authority: escrow.to_account_info(),
+            },
+            signer,
+        ))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = payer, space = ArbiterConfig::LEN, seeds = [b"arbiter_config"], bump)]
+    pub config: Account<'info, ArbiterConfig>,
+
+    pub multisig: Account<'info, governance::Multisig>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetArbiterMultisig<'info> {
+    #[account(mut, seeds = [b"arbiter_config"], bump = config.bump)]
+    pub config: Account<'info, ArbiterConfig>,
+
+    // The current multisig's signer PDA
+    #[account(address = multisig_signer(&config.multisig) @ FreelanceError::Unauthorized)]
+    pub arbiter: Signer<'info>,
+
+    pub new_multisig: Account<'info, governance::Multisig>,
+}
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64)]
+pub struct CreateEscrow<'info> {
+    #[account(
+        init,
+        payer = client,
+        space = Escrow::LEN,
+        seeds = [b"escrow", client.key().as_ref(), &escrow_id.to_le_bytes()],
+        bump,
+    )]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        init,
+        payer = client,
+        token::mint = mint,
+        token::authority = escrow,
+        seeds = [b"escrow_vault", escrow.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == client.key() @ FreelanceError::InvalidTokenAccount,
+        constraint = client_token_account.mint == mint.key() @ FreelanceError::InvalidTokenAccount,
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"arbiter_config"], bump = config.bump)]
+    pub config: Account<'info, ArbiterConfig>,
+
+    #[account(address = config.multisig @ FreelanceError::InvalidArbiter)]
+    pub multisig: Account<'info, governance::Multisig>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveMilestone<'info> {
+    #[account(mut, has_one = client @ FreelanceError::Unauthorized)]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", escrow.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = contractor_token_account.owner == escrow.contractor @ FreelanceError::InvalidTokenAccount,
+        constraint = contractor_token_account.mint == escrow.mint @ FreelanceError::InvalidTokenAccount,
+    )]
+    pub contractor_token_account: Account<'info, TokenAccount>,
+
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DisputeMilestone<'info> {
+    #[account(mut)]
+    pub escrow: Account<'info, Escrow>,
+
+    pub party: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    #[account(mut, has_one = arbiter @ FreelanceError::Unauthorized)]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", escrow.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = contractor_token_account.owner == escrow.contractor @ FreelanceError::InvalidTokenAccount,
+        constraint = contractor_token_account.mint == escrow.mint @ FreelanceError::InvalidTokenAccount,
+    )]
+    pub contractor_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == escrow.client @ FreelanceError::InvalidTokenAccount,
+        constraint = client_token_account.mint == escrow.mint @ FreelanceError::InvalidTokenAccount,
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    // The governance multisig's signer PDA, signing through `execute_transaction`
+    pub arbiter: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RefundMilestone<'info> {
+    #[account(mut, has_one = client @ FreelanceError::Unauthorized)]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", escrow.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == escrow.client @ FreelanceError::InvalidTokenAccount,
+        constraint = client_token_account.mint == escrow.mint @ FreelanceError::InvalidTokenAccount,
+    )]
+    pub client_token_account: Account<'info, TokenAccount>,
+
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEscrow<'info> {
+    #[account(mut, close = client, has_one = client @ FreelanceError::Unauthorized)]
+    pub escrow: Account<'info, Escrow>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_vault", escrow.key().as_ref()],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MilestoneInput {
+    pub amount: u64,
+    pub deadline: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MilestoneStatus {
+    Pending,
+    Disputed,
+    Released,
+    Resolved,
+    Refunded,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Milestone {
+    pub amount: u64,
+    pub deadline: i64,
+    pub status: MilestoneStatus,
+}
+
+impl Milestone {
+    pub const LEN: usize = 8 + // amount
+        8 + // deadline
+        1; // status
+
+    pub fn is_settled(&self) -> bool {
+        matches!(
+            self.status,
+            MilestoneStatus::Released | MilestoneStatus::Resolved | MilestoneStatus::Refunded
+        )
+    }
+}
+
+#[account]
+pub struct ArbiterConfig {
+    pub multisig: Pubkey,
+    pub bump: u8,
+}
+
+impl ArbiterConfig {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // multisig
+        1; // bump
+}
+
+#[account]
+pub struct Escrow {
+    pub client: Pubkey,
+    pub contractor: Pubkey,
+    pub arbiter: Pubkey,
+    pub mint: Pubkey,
+    pub escrow_id: u64,
+    pub total_amount: u64,
+    pub released_amount: u64,
+    pub milestones: Vec<Milestone>,
+    pub bump: u8,
+}
+
+impl Escrow {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // client
+        32 + // contractor
+        32 + // arbiter
+        32 + // mint
+        8 + // escrow_id
+        8 + // total_amount
+        8 + // released_amount
+        4 + MAX_MILESTONES * Milestone::LEN + // milestones
+        1; // bump
+
+    fn milestone_mut(&mut self, index: u8) -> Result<&mut Milestone> {
+        self.milestones
+            .get_mut(index as usize)
+            .ok_or(error!(FreelanceError::InvalidMilestoneIndex))
+    }
+
+    fn record_release(&mut self, amount: u64) -> Result<()> {
+        self.released_amount = self.released_amount
+            .checked_add(amount)
+            .ok_or(FreelanceError::ArithmeticError)?;
+        Ok(())
+    }
+}
+
+// Signer PDA a governance multisig signs its executed transactions with
+fn multisig_signer(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"multisig_signer", multisig.as_ref()], &governance::ID).0
+}
+
+// Pays out of the vault with the escrow PDA as signer
+fn release_from_vault<'info>(
+    escrow: &Account<'info, Escrow>,
+    token_program: &Program<'info, Token>,
+    vault: &Account<'info, TokenAccount>,
+    to: &Account<'info, TokenAccount>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let id_bytes = escrow.escrow_id.to_le_bytes();
+    let seeds = &[b"escrow", escrow.client.as_ref(), id_bytes.as_ref(), &[escrow.bump]];
+    let signer = &[&seeds[..]];
+
+    transfer_helper::transfer_tokens_signed(
+        &token_program.to_account_info(),
+        &vault.to_account_info(),
+        &to.to_account_info(),
+        &escrow.to_account_info(),
+        signer,
+        amount,
+    )
+}
+
+// Events
+#[event]
+pub struct EscrowCreatedEvent {
+    pub escrow: Pubkey,
+    pub client: Pubkey,
+    pub contractor: Pubkey,
+    pub mint: Pubkey,
+    pub total_amount: u64,
+}
+
+#[event]
+pub struct MilestoneDisputedEvent {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub raised_by: Pubkey,
+}
+
+#[event]
+pub struct MilestoneSettledEvent {
+    pub escrow: Pubkey,
+    pub index: u8,
+    pub to_contractor: u64,
+    pub to_client: u64,
+}
+
+#[error_code]
+pub enum FreelanceError {
+    #[msg("Unauthorized access")]
+    Unauthorized,
+
+    #[msg("Milestones must be non-empty, funded and due in the future")]
+    InvalidMilestones,
+
+    #[msg("Contractor cannot be the client")]
+    InvalidContractor,
+
+    #[msg("Milestone index out of range")]
+    InvalidMilestoneIndex,
+
+    #[msg("Milestone is not in the required status")]
+    InvalidMilestoneStatus,
+
+    #[msg("Milestone deadline has not passed yet")]
+    DeadlineNotReached,
+
+    #[msg("Token account owner or mint does not match the escrow")]
+    InvalidTokenAccount,
+
+    #[msg("Invalid amount")]
+    InvalidAmount,
+
+    #[msg("All milestones must be settled before closing")]
+    EscrowNotSettled,
+
+    #[msg("Arithmetic operation failed due to overflow or underflow")]
+    ArithmeticError,
+
+    #[msg("Multisig is not the configured arbiter")]
+    InvalidArbiter,
+}
@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::{instruction::Instruction, program::invoke_signed};
+use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("Governance111111111111111111111111111111111");
 
@@ -11,15 +12,17 @@ pub mod governance {
         let governance = &mut ctx.accounts.governance;
         governance.authority = ctx.accounts.authority.key();
         governance.counter = 0;
+        governance.proposal_count = 0;
+        governance.stream_count = 0;
         Ok(())
     }
 
-    // Simple function to increment a counter
-    pub fn increment_counter(ctx: Context<UpdateGovernance>) -> Result<()> {
+    // Simple function to increment a counter, returns the new value
+    pub fn increment_counter(ctx: Context<UpdateGovernance>) -> Result<u64> {
         let governance = &mut ctx.accounts.governance;
         governance.counter += 1;
         msg!("Counter incremented to: {}", governance.counter);
-        Ok(())
+        Ok(governance.counter)
     }
 
     // Function to receive tokens
@@ -41,8 +44,11 @@ pub mod governance {
         Ok(())
     }
 
-    // Function to send tokens
+    // Function to send tokens - only the governance authority can move treasury funds directly
     pub fn send_tokens(ctx: Context<SendTokens>, amount: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.spending_limit.consume(amount, now)?;
+
         // Get PDA signer seeds
         let governance_key = ctx.accounts.governance.key();
         let seeds = &[b"governance", governance_key.as_ref(), &[ctx.bumps.governance_authority]];
@@ -62,10 +68,462 @@ pub mod governance {
             amount,
         )?;
 
+        emit!(TreasuryOutflowEvent {
+            governance: governance_key,
+            mint: ctx.accounts.governance_token_account.mint,
+            recipient: ctx.accounts.recipient_token_account.owner,
+            recipient_token_account: ctx.accounts.recipient_token_account.key(),
+            amount,
+            proposal: Pubkey::default(),
+        });
+
         msg!("Sent {} tokens", amount);
         Ok(())
     }
 
+    // Set the maximum amount of a mint that can leave the treasury per period
+    pub fn set_spending_limit(
+        ctx: Context<SetSpendingLimit>,
+        amount_per_period: u64,
+        period: i64,
+    ) -> Result<()> {
+        require!(period > 0, GovernanceError::InvalidPeriod);
+
+        let spending_limit = &mut ctx.accounts.spending_limit;
+        spending_limit.governance = ctx.accounts.governance.key();
+        spending_limit.mint = ctx.accounts.mint.key();
+        spending_limit.amount_per_period = amount_per_period;
+        spending_limit.period = period;
+        spending_limit.bump = ctx.bumps.spending_limit;
+
+        msg!("Spending limit for {} set to {} every {}s", spending_limit.mint, amount_per_period, period);
+        Ok(())
+    }
+
+    // Anyone can propose an action, it only takes effect once approved and executed
+    pub fn create_proposal(ctx: Context<CreateProposal>, action: ProposalAction) -> Result<()> {
+        if let ProposalAction::SetConfig { key, .. } = &action {
+            require!(key.len() <= MAX_CONFIG_KEY_LEN, GovernanceError::InvalidConfigKey);
+        }
+
+        let governance = &mut ctx.accounts.governance;
+        let proposal = &mut ctx.accounts.proposal;
+
+        proposal.governance = governance.key();
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.index = governance.proposal_count;
+        proposal.action = action;
+        proposal.status = ProposalStatus::Pending;
+        proposal.created_at = Clock::get()?.unix_timestamp;
+        proposal.executed_at = 0;
+
+        governance.proposal_count = governance.proposal_count
+            .checked_add(1)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        msg!("Proposal {} created", proposal.index);
+        Ok(())
+    }
+
+    pub fn approve_proposal(ctx: Context<ReviewProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(proposal.status == ProposalStatus::Pending, GovernanceError::InvalidProposalStatus);
+        proposal.status = ProposalStatus::Approved;
+
+        msg!("Proposal {} approved", proposal.index);
+        Ok(())
+    }
+
+    pub fn reject_proposal(ctx: Context<ReviewProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.status == ProposalStatus::Pending || proposal.status == ProposalStatus::Approved,
+            GovernanceError::InvalidProposalStatus
+        );
+        proposal.status = ProposalStatus::Rejected;
+
+        msg!("Proposal {} rejected", proposal.index);
+        Ok(())
+    }
+
+    // Executes an approved transfer proposal, callable by anyone
+    pub fn execute_transfer_proposal(ctx: Context<ExecuteTransferProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(proposal.status == ProposalStatus::Approved, GovernanceError::InvalidProposalStatus);
+
+        let (mint, recipient_token_account, amount) = match &proposal.action {
+            ProposalAction::Transfer { mint, recipient_token_account, amount } => {
+                (*mint, *recipient_token_account, *amount)
+            }
+            _ => return err!(GovernanceError::InvalidProposalAction),
+        };
+        require!(
+            ctx.accounts.governance_token_account.mint == mint,
+            GovernanceError::ProposalAccountMismatch
+        );
+        require!(
+            ctx.accounts.recipient_token_account.key() == recipient_token_account,
+            GovernanceError::ProposalAccountMismatch
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.spending_limit.consume(amount, now)?;
+
+        // Mark as executed before moving funds so the proposal can't be replayed
+        proposal.status = ProposalStatus::Executed;
+        proposal.executed_at = now;
+
+        let governance_key = ctx.accounts.governance.key();
+        let seeds = &[b"governance", governance_key.as_ref(), &[ctx.bumps.governance_authority]];
+        let signer = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.governance_token_account.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: ctx.accounts.governance_authority.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+
+        emit!(TreasuryOutflowEvent {
+            governance: governance_key,
+            mint,
+            recipient: ctx.accounts.recipient_token_account.owner,
+            recipient_token_account,
+            amount,
+            proposal: proposal.key(),
+        });
+
+        msg!("Proposal {} executed, sent {} tokens", proposal.index, amount);
+        Ok(())
+    }
+
+    // Escrow a payment stream from the treasury, the recipient withdraws as it accrues
+    pub fn create_stream(
+        ctx: Context<CreateStream>,
+        recipient: Pubkey,
+        rate_per_second: u64,
+        start_ts: i64,
+        end_ts: i64,
+        cancellable: bool,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(rate_per_second > 0, GovernanceError::InvalidStream);
+        require!(end_ts > start_ts && end_ts > now, GovernanceError::InvalidStream);
+
+        let duration = end_ts - start_ts;
+        let total = rate_per_second
+            .checked_mul(duration as u64)
+            .ok_or(GovernanceError::ArithmeticError)?;
+        ctx.accounts.spending_limit.consume(total, now)?;
+
+        let governance = &mut ctx.accounts.governance;
+        let stream = &mut ctx.accounts.stream;
+        stream.governance = governance.key();
+        stream.index = governance.stream_count;
+        stream.recipient = recipient;
+        stream.mint = ctx.accounts.mint.key();
+        stream.rate_per_second = rate_per_second;
+        stream.start_ts = start_ts;
+        stream.end_ts = end_ts;
+        stream.withdrawn = 0;
+        stream.cancellable = cancellable;
+        stream.cancelled = false;
+        stream.bump = ctx.bumps.stream;
+
+        governance.stream_count = governance.stream_count
+            .checked_add(1)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        let governance_key = governance.key();
+        let seeds = &[b"governance", governance_key.as_ref(), &[ctx.bumps.governance_authority]];
+        let signer = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.governance_token_account.to_account_info(),
+                    to: ctx.accounts.stream_vault.to_account_info(),
+                    authority: ctx.accounts.governance_authority.to_account_info(),
+                },
+                signer,
+            ),
+            total,
+        )?;
+
+        emit!(TreasuryOutflowEvent {
+            governance: governance_key,
+            mint: stream.mint,
+            recipient,
+            recipient_token_account: ctx.accounts.stream_vault.key(),
+            amount: total,
+            proposal: Pubkey::default(),
+        });
+
+        msg!("Stream {} created, {} tokens escrowed", stream.index, total);
+        Ok(())
+    }
+
+    // Recipient pulls whatever has accrued since the last withdrawal
+    pub fn withdraw_from_stream(ctx: Context<WithdrawFromStream>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let stream = &mut ctx.accounts.stream;
+
+        let amount = stream.accrued(now)?
+            .checked_sub(stream.withdrawn)
+            .ok_or(GovernanceError::ArithmeticError)?;
+        require!(amount > 0, GovernanceError::NothingToWithdraw);
+
+        stream.withdrawn = stream.withdrawn
+            .checked_add(amount)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        let governance_key = stream.governance;
+        let index_bytes = stream.index.to_le_bytes();
+        let seeds = &[b"stream", governance_key.as_ref(), index_bytes.as_ref(), &[stream.bump]];
+        let signer = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.stream_vault.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: stream.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+
+        emit!(StreamWithdrawEvent {
+            stream: stream.key(),
+            recipient: stream.recipient,
+            amount,
+        });
+
+        msg!("Withdrew {} tokens from stream {}", amount, stream.index);
+        Ok(())
+    }
+
+    // Stops accrual now and returns the unaccrued remainder to the treasury
+    pub fn cancel_stream(ctx: Context<CancelStream>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let stream = &mut ctx.accounts.stream;
+        require!(stream.cancellable, GovernanceError::StreamNotCancellable);
+        require!(!stream.cancelled, GovernanceError::StreamAlreadyCancelled);
+
+        let new_end = now.clamp(stream.start_ts, stream.end_ts);
+        let remainder = stream.rate_per_second
+            .checked_mul((stream.end_ts - new_end) as u64)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        stream.end_ts = new_end;
+        stream.cancelled = true;
+
+        if remainder > 0 {
+            let governance_key = stream.governance;
+            let index_bytes = stream.index.to_le_bytes();
+            let seeds = &[b"stream", governance_key.as_ref(), index_bytes.as_ref(), &[stream.bump]];
+            let signer = &[&seeds[..]];
+
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.stream_vault.to_account_info(),
+                        to: ctx.accounts.governance_token_account.to_account_info(),
+                        authority: stream.to_account_info(),
+                    },
+                    signer,
+                ),
+                remainder,
+            )?;
+        }
+
+        emit!(StreamCancelledEvent {
+            stream: stream.key(),
+            recipient: stream.recipient,
+            reclaimed: remainder,
+        });
+
+        msg!("Stream {} cancelled, {} tokens reclaimed", stream.index, remainder);
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.governance = ctx.accounts.governance.key();
+        config.entries = Vec::new();
+        config.bump = ctx.bumps.config;
+        Ok(())
+    }
+
+    // Adds a parameter or changes its bounds, the current value must fit the new bounds
+    pub fn define_config(
+        ctx: Context<UpdateConfig>,
+        key: String,
+        value: ConfigValue,
+        min: u64,
+        max: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.define(key, value, min, max)
+    }
+
+    // Changes a parameter value within its bounds
+    pub fn set_config(ctx: Context<UpdateConfig>, key: String, value: ConfigValue) -> Result<()> {
+        ctx.accounts.config.set(&key, value)
+    }
+
+    // Applies an approved config proposal, callable by anyone
+    pub fn execute_config_proposal(ctx: Context<ExecuteConfigProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(proposal.status == ProposalStatus::Approved, GovernanceError::InvalidProposalStatus);
+
+        let (key, value) = match &proposal.action {
+            ProposalAction::SetConfig { key, value } => (key.clone(), value.clone()),
+            _ => return err!(GovernanceError::InvalidProposalAction),
+        };
+        ctx.accounts.config.set(&key, value)?;
+
+        proposal.status = ProposalStatus::Executed;
+        proposal.executed_at = Clock::get()?.unix_timestamp;
+
+        msg!("Proposal {} executed, config {} updated", proposal.index, key);
+        Ok(())
+    }
+
+    // M-of-N multisig whose signer PDA can act as Dextra's owner or governance
+    pub fn create_multisig(ctx: Context<CreateMultisig>, signers: Vec<Pubkey>, threshold: u8) -> Result<()> {
+        validate_signers(&signers, threshold)?;
+
+        let multisig = &mut ctx.accounts.multisig;
+        multisig.signers = signers;
+        multisig.threshold = threshold;
+        multisig.signer_set_seqno = 0;
+        multisig.transaction_count = 0;
+        multisig.signer_bump = ctx.bumps.multisig_signer;
+
+        msg!("Multisig created with threshold {}", threshold);
+        Ok(())
+    }
+
+    // A multisig signer proposes an instruction for the multisig PDA to sign, counting as the first approval
+    pub fn propose_transaction(
+        ctx: Context<ProposeTransaction>,
+        program_id: Pubkey,
+        accounts: Vec<TransactionAccount>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let proposer_index = multisig.signer_index(&ctx.accounts.proposer.key())?;
+
+        let transaction = &mut ctx.accounts.transaction;
+        transaction.multisig = multisig.key();
+        transaction.index = multisig.transaction_count;
+        transaction.proposer = ctx.accounts.proposer.key();
+        transaction.program_id = program_id;
+        transaction.accounts = accounts;
+        transaction.data = data;
+        transaction.approvals = vec![false; multisig.signers.len()];
+        transaction.approvals[proposer_index] = true;
+        transaction.executed = false;
+        transaction.signer_set_seqno = multisig.signer_set_seqno;
+
+        multisig.transaction_count = multisig.transaction_count
+            .checked_add(1)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        msg!("Multisig transaction {} proposed", transaction.index);
+        Ok(())
+    }
+
+    pub fn approve_transaction(ctx: Context<ApproveTransaction>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+        require!(!transaction.executed, GovernanceError::TransactionAlreadyExecuted);
+        require!(
+            transaction.signer_set_seqno == multisig.signer_set_seqno,
+            GovernanceError::StaleTransaction
+        );
+
+        let signer_index = multisig.signer_index(&ctx.accounts.signer.key())?;
+        transaction.approvals[signer_index] = true;
+
+        msg!("Multisig transaction {} approved by {}", transaction.index, ctx.accounts.signer.key());
+        Ok(())
+    }
+
+    // Once the threshold is met anyone can execute, the multisig PDA signs the inner instruction
+    pub fn execute_transaction<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteTransaction<'info>>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+        require!(!transaction.executed, GovernanceError::TransactionAlreadyExecuted);
+        require!(
+            transaction.signer_set_seqno == multisig.signer_set_seqno,
+            GovernanceError::StaleTransaction
+        );
+
+        let approvals = transaction.approvals.iter().filter(|approved| **approved).count();
+        require!(approvals >= multisig.threshold as usize, GovernanceError::NotEnoughApprovals);
+
+        // Anchor only writes accounts back when the instruction returns, persist the flag
+        // now so the inner instruction already sees the transaction as executed
+        transaction.executed = true;
+        transaction.exit(ctx.program_id)?;
+
+        let multisig_signer = ctx.accounts.multisig_signer.key();
+        let ix = Instruction {
+            program_id: transaction.program_id,
+            accounts: transaction.accounts
+                .iter()
+                .map(|account| {
+                    let is_signer = account.is_signer || account.pubkey == multisig_signer;
+                    if account.is_writable {
+                        AccountMeta::new(account.pubkey, is_signer)
+                    } else {
+                        AccountMeta::new_readonly(account.pubkey, is_signer)
+                    }
+                })
+                .collect(),
+            data: transaction.data.clone(),
+        };
+
+        let multisig_key = multisig.key();
+        let seeds = &[b"multisig_signer", multisig_key.as_ref(), &[multisig.signer_bump]];
+        let signer = &[&seeds[..]];
+        invoke_signed(&ix, ctx.remaining_accounts, signer)?;
+
+        msg!("Multisig transaction {} executed", transaction.index);
+        Ok(())
+    }
+
+    // Only reachable through an executed multisig transaction, so it needs the current threshold
+    pub fn set_multisig_signers(
+        ctx: Context<SetMultisigSigners>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        validate_signers(&signers, threshold)?;
+
+        let multisig = &mut ctx.accounts.multisig;
+        multisig.signers = signers;
+        multisig.threshold = threshold;
+        // Pending transactions were approved by the old signer set and can no longer execute
+        multisig.signer_set_seqno = multisig.signer_set_seqno
+            .checked_add(1)
+            .ok_or(GovernanceError::ArithmeticError)?;
+
+        msg!("Multisig signers updated, threshold {}", threshold);
+        Ok(())
+    }
+
     // Function that will fail - for testing error handling
     pub fn will_fail(_ctx: Context<UpdateGovernance>) -> Result<()> {
         return err!(GovernanceError::IntentionalFailure);
@@ -77,7 +535,7 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = authority,
-        space = 8 + 32 + 8, // discriminator + pubkey + counter
+        space = GovernanceState::LEN,
     )]
     pub governance: Account<'info, GovernanceState>,
     
@@ -116,7 +574,10 @@ pub struct ReceiveTokens<'info> {
 
 #[derive(Accounts)]
 pub struct SendTokens<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
     pub governance: Account<'info, GovernanceState>,
+
+    pub authority: Signer<'info>,
     
     #[account(
         seeds = [b"governance", governance.key().as_ref()],
@@ -124,19 +585,706 @@ pub struct SendTokens<'info> {
     )]
     pub governance_authority: SystemAccount<'info>,
     
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = governance_token_account.owner == governance_authority.key() @ GovernanceError::InvalidTreasuryAccount,
+    )]
     pub governance_token_account: Account<'info, TokenAccount>,
     
     #[account(mut)]
     pub recipient_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"spending_limit", governance.key().as_ref(), governance_token_account.mint.as_ref()],
+        bump = spending_limit.bump,
+    )]
+    pub spending_limit: Account<'info, SpendingLimit>,
     
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct SetSpendingLimit<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = SpendingLimit::LEN,
+        seeds = [b"spending_limit", governance.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub spending_limit: Account<'info, SpendingLimit>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = Proposal::LEN,
+        seeds = [b"proposal", governance.key().as_ref(), &governance.proposal_count.to_le_bytes()],
+        bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReviewProposal<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTransferProposal<'info> {
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        seeds = [b"governance", governance.key().as_ref()],
+        bump,
+    )]
+    pub governance_authority: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = governance_token_account.owner == governance_authority.key() @ GovernanceError::InvalidTreasuryAccount,
+    )]
+    pub governance_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"spending_limit", governance.key().as_ref(), governance_token_account.mint.as_ref()],
+        bump = spending_limit.bump,
+    )]
+    pub spending_limit: Account<'info, SpendingLimit>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateStream<'info> {
+    #[account(mut, has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = PaymentStream::LEN,
+        seeds = [b"stream", governance.key().as_ref(), &governance.stream_count.to_le_bytes()],
+        bump,
+    )]
+    pub stream: Account<'info, PaymentStream>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = mint,
+        token::authority = stream,
+        seeds = [b"stream_vault", stream.key().as_ref()],
+        bump,
+    )]
+    pub stream_vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"governance", governance.key().as_ref()],
+        bump,
+    )]
+    pub governance_authority: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = governance_token_account.owner == governance_authority.key() @ GovernanceError::InvalidTreasuryAccount,
+        constraint = governance_token_account.mint == mint.key() @ GovernanceError::InvalidTreasuryAccount,
+    )]
+    pub governance_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"spending_limit", governance.key().as_ref(), mint.key().as_ref()],
+        bump = spending_limit.bump,
+    )]
+    pub spending_limit: Account<'info, SpendingLimit>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromStream<'info> {
+    #[account(mut, has_one = recipient @ GovernanceError::Unauthorized)]
+    pub stream: Account<'info, PaymentStream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream.key().as_ref()],
+        bump,
+    )]
+    pub stream_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = recipient_token_account.mint == stream.mint @ GovernanceError::InvalidStream,
+    )]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+
+    pub recipient: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelStream<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(mut, has_one = governance)]
+    pub stream: Account<'info, PaymentStream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream.key().as_ref()],
+        bump,
+    )]
+    pub stream_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        seeds = [b"governance", governance.key().as_ref()],
+        bump,
+    )]
+    pub governance_authority: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = governance_token_account.owner == governance_authority.key() @ GovernanceError::InvalidTreasuryAccount,
+    )]
+    pub governance_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = ConfigRegistry::LEN,
+        seeds = [b"config", governance.key().as_ref()],
+        bump,
+    )]
+    pub config: Account<'info, ConfigRegistry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(has_one = authority @ GovernanceError::Unauthorized)]
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(
+        mut,
+        has_one = governance,
+        seeds = [b"config", governance.key().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ConfigRegistry>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteConfigProposal<'info> {
+    pub governance: Account<'info, GovernanceState>,
+
+    #[account(mut, has_one = governance)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        has_one = governance,
+        seeds = [b"config", governance.key().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ConfigRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct CreateMultisig<'info> {
+    #[account(init, payer = payer, space = Multisig::LEN)]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        seeds = [b"multisig_signer", multisig.key().as_ref()],
+        bump,
+    )]
+    pub multisig_signer: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(program_id: Pubkey, accounts: Vec<TransactionAccount>, data: Vec<u8>)]
+pub struct ProposeTransaction<'info> {
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = MultisigTransaction::space(accounts.len(), data.len(), multisig.signers.len()),
+        seeds = [b"multisig_tx", multisig.key().as_ref(), &multisig.transaction_count.to_le_bytes()],
+        bump,
+    )]
+    pub transaction: Account<'info, MultisigTransaction>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveTransaction<'info> {
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(mut, has_one = multisig)]
+    pub transaction: Account<'info, MultisigTransaction>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTransaction<'info> {
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        seeds = [b"multisig_signer", multisig.key().as_ref()],
+        bump = multisig.signer_bump,
+    )]
+    pub multisig_signer: SystemAccount<'info>,
+
+    #[account(mut, has_one = multisig)]
+    pub transaction: Account<'info, MultisigTransaction>,
+}
+
+#[derive(Accounts)]
+pub struct SetMultisigSigners<'info> {
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        seeds = [b"multisig_signer", multisig.key().as_ref()],
+        bump = multisig.signer_bump,
+    )]
+    pub multisig_signer: Signer<'info>,
+}
+
 #[account]
 pub struct GovernanceState {
     pub authority: Pubkey,
     pub counter: u64,
+    pub proposal_count: u64,
+    pub stream_count: u64,
+}
+
+impl GovernanceState {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // authority
+        8 + // counter
+        8 + // proposal_count
+        8; // stream_count
+}
+
+// Per-mint cap on treasury outflows, reset every `period` seconds
+#[account]
+pub struct SpendingLimit {
+    pub governance: Pubkey,
+    pub mint: Pubkey,
+    pub amount_per_period: u64,
+    pub period: i64,
+    pub period_start: i64,
+    pub spent_in_period: u64,
+    pub bump: u8,
+}
+
+impl SpendingLimit {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // governance
+        32 + // mint
+        8 + // amount_per_period
+        8 + // period
+        8 + // period_start
+        8 + // spent_in_period
+        1; // bump
+
+    pub fn consume(&mut self, amount: u64, now: i64) -> Result<()> {
+        let period_end = self.period_start
+            .checked_add(self.period)
+            .ok_or(GovernanceError::ArithmeticError)?;
+        if now >= period_end {
+            self.period_start = now;
+            self.spent_in_period = 0;
+        }
+
+        let spent = self.spent_in_period
+            .checked_add(amount)
+            .ok_or(GovernanceError::ArithmeticError)?;
+        require!(spent <= self.amount_per_period, GovernanceError::SpendingLimitExceeded);
+        self.spent_in_period = spent;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum ProposalAction {
+    Transfer {
+        mint: Pubkey,
+        recipient_token_account: Pubkey,
+        amount: u64,
+    },
+    SetConfig {
+        key: String,
+        value: ConfigValue,
+    },
+}
+
+impl ProposalAction {
+    // Sized for the largest variant (Transfer)
+    pub const MAX_LEN: usize = 1 + // variant
+        32 + // mint
+        32 + // recipient_token_account
+        8; // amount
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProposalStatus {
+    Pending,
+    Approved,
+    Executed,
+    Rejected,
+}
+
+#[account]
+pub struct Proposal {
+    pub governance: Pubkey,
+    pub proposer: Pubkey,
+    pub index: u64,
+    pub action: ProposalAction,
+    pub status: ProposalStatus,
+    pub created_at: i64,
+    pub executed_at: i64,
+}
+
+impl Proposal {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // governance
+        32 + // proposer
+        8 + // index
+        ProposalAction::MAX_LEN + // action
+        1 + // status
+        8 + // created_at
+        8; // executed_at
+}
+
+// Tokens escrowed from the treasury and released linearly between start_ts and end_ts
+#[account]
+pub struct PaymentStream {
+    pub governance: Pubkey,
+    pub index: u64,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub rate_per_second: u64,
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub withdrawn: u64,
+    pub cancellable: bool,
+    pub cancelled: bool,
+    pub bump: u8,
+}
+
+impl PaymentStream {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // governance
+        8 + // index
+        32 + // recipient
+        32 + // mint
+        8 + // rate_per_second
+        8 + // start_ts
+        8 + // end_ts
+        8 + // withdrawn
+        1 + // cancellable
+        1 + // cancelled
+        1; // bump
+
+    pub fn accrued(&self, now: i64) -> Result<u64> {
+        let elapsed = now.clamp(self.start_ts, self.end_ts) - self.start_ts;
+        Ok(self.rate_per_second
+            .checked_mul(elapsed as u64)
+            .ok_or(GovernanceError::ArithmeticError)?)
+    }
+}
+
+// Well-known registry keys read by Dextra
+pub const MAX_APY_KEY: &str = "max_apy";
+pub const MAX_REF_PERCENT_KEY: &str = "max_ref_percent";
+
+pub const MAX_CONFIG_ENTRIES: usize = 32;
+pub const MAX_CONFIG_KEY_LEN: usize = 32;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    U64(u64),
+    Pubkey(Pubkey),
+    Bool(bool),
+}
+
+impl ConfigValue {
+    pub const MAX_LEN: usize = 1 + 32; // variant + largest payload
+
+    fn same_type(&self, other: &ConfigValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+// `min`/`max` only constrain U64 values
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: ConfigValue,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl ConfigEntry {
+    pub const LEN: usize = 4 + MAX_CONFIG_KEY_LEN + // key
+        ConfigValue::MAX_LEN + // value
+        8 + // min
+        8; // max
+
+    fn check_bounds(&self, value: &ConfigValue) -> Result<()> {
+        if let ConfigValue::U64(number) = value {
+            require!(
+                *number >= self.min && *number <= self.max,
+                GovernanceError::ConfigValueOutOfBounds
+            );
+        }
+        Ok(())
+    }
+}
+
+#[account]
+pub struct ConfigRegistry {
+    pub governance: Pubkey,
+    pub entries: Vec<ConfigEntry>,
+    pub bump: u8,
+}
+
+impl ConfigRegistry {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // governance
+        4 + MAX_CONFIG_ENTRIES * ConfigEntry::LEN + // entries
+        1; // bump
+
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        match self.get(key) {
+            Some(ConfigValue::U64(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_pubkey(&self, key: &str) -> Option<Pubkey> {
+        match self.get(key) {
+            Some(ConfigValue::Pubkey(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key) {
+            Some(ConfigValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn define(&mut self, key: String, value: ConfigValue, min: u64, max: u64) -> Result<()> {
+        require!(!key.is_empty() && key.len() <= MAX_CONFIG_KEY_LEN, GovernanceError::InvalidConfigKey);
+        require!(min <= max, GovernanceError::ConfigValueOutOfBounds);
+
+        let entry = ConfigEntry { key, value, min, max };
+        entry.check_bounds(&entry.value)?;
+
+        if let Some(pos) = self.entries.iter().position(|existing| existing.key == entry.key) {
+            self.entries[pos] = entry;
+        } else {
+            require!(self.entries.len() < MAX_CONFIG_ENTRIES, GovernanceError::ConfigRegistryFull);
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: ConfigValue) -> Result<()> {
+        let entry = self.entries
+            .iter_mut()
+            .find(|entry| entry.key == key)
+            .ok_or(GovernanceError::InvalidConfigKey)?;
+        require!(entry.value.same_type(&value), GovernanceError::ConfigTypeMismatch);
+        entry.check_bounds(&value)?;
+        entry.value = value;
+        Ok(())
+    }
+}
+
+pub const MAX_MULTISIG_SIGNERS: usize = 10;
+
+#[account]
+pub struct Multisig {
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub signer_set_seqno: u32,
+    pub transaction_count: u64,
+    pub signer_bump: u8,
+}
+
+impl Multisig {
+    pub const LEN: usize = 8 + // discriminator
+        4 + MAX_MULTISIG_SIGNERS * 32 + // signers
+        1 + // threshold
+        4 + // signer_set_seqno
+        8 + // transaction_count
+        1; // signer_bump
+
+    fn signer_index(&self, key: &Pubkey) -> Result<usize> {
+        self.signers
+            .iter()
+            .position(|signer| signer == key)
+            .ok_or(error!(GovernanceError::NotMultisigSigner))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TransactionAccount {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[account]
+pub struct MultisigTransaction {
+    pub multisig: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub program_id: Pubkey,
+    pub accounts: Vec<TransactionAccount>,
+    pub data: Vec<u8>,
+    pub approvals: Vec<bool>,
+    pub executed: bool,
+    pub signer_set_seqno: u32,
+}
+
+impl MultisigTransaction {
+    pub fn space(accounts: usize, data: usize, signers: usize) -> usize {
+        8 + // discriminator
+        32 + // multisig
+        8 + // index
+        32 + // proposer
+        32 + // program_id
+        4 + accounts * (32 + 1 + 1) + // accounts
+        4 + data + // data
+        4 + signers + // approvals
+        1 + // executed
+        4 // signer_set_seqno
+    }
+}
+
+// Events
+#[event]
+pub struct TreasuryOutflowEvent {
+    pub governance: Pubkey,
+    pub mint: Pubkey,
+    pub recipient: Pubkey,
+    pub recipient_token_account: Pubkey,
+    pub amount: u64,
+    pub proposal: Pubkey,
+}
+
+#[event]
+pub struct StreamWithdrawEvent {
+    pub stream: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct StreamCancelledEvent {
+    pub stream: Pubkey,
+    pub recipient: Pubkey,
+    pub reclaimed: u64,
+}
+
+fn validate_signers(signers: &[Pubkey], threshold: u8) -> Result<()> {
+    require!(
+        !signers.is_empty() && signers.len() <= MAX_MULTISIG_SIGNERS,
+        GovernanceError::InvalidMultisigSigners
+    );
+    for (i, signer) in signers.iter().enumerate() {
+        require!(!signers[..i].contains(signer), GovernanceError::InvalidMultisigSigners);
+    }
+    require!(
+        threshold > 0 && threshold as usize <= signers.len(),
+        GovernanceError::InvalidThreshold
+    );
+    Ok(())
 }
 
 #[error_code]
@@ -146,4 +1294,67 @@ pub enum GovernanceError {
     
     #[msg("Intentional failure for testing")]
     IntentionalFailure,
+
+    #[msg("Treasury spending limit exceeded for this period")]
+    SpendingLimitExceeded,
+
+    #[msg("Period must be greater than zero")]
+    InvalidPeriod,
+
+    #[msg("Token account is not owned by the governance treasury")]
+    InvalidTreasuryAccount,
+
+    #[msg("Proposal is not in the required status")]
+    InvalidProposalStatus,
+
+    #[msg("Accounts do not match the proposal")]
+    ProposalAccountMismatch,
+
+    #[msg("Arithmetic operation failed due to overflow or underflow")]
+    ArithmeticError,
+
+    #[msg("Invalid stream parameters")]
+    InvalidStream,
+
+    #[msg("Nothing to withdraw")]
+    NothingToWithdraw,
+
+    #[msg("Stream is not cancellable")]
+    StreamNotCancellable,
+
+    #[msg("Stream already cancelled")]
+    StreamAlreadyCancelled,
+
+    #[msg("Proposal action does not match this instruction")]
+    InvalidProposalAction,
+
+    #[msg("Unknown or invalid config key")]
+    InvalidConfigKey,
+
+    #[msg("Config value type does not match the entry")]
+    ConfigTypeMismatch,
+
+    #[msg("Config value outside of its bounds")]
+    ConfigValueOutOfBounds,
+
+    #[msg("Config registry is full")]
+    ConfigRegistryFull,
+
+    #[msg("Signer set must be non-empty, unique and within the maximum size")]
+    InvalidMultisigSigners,
+
+    #[msg("Threshold must be between 1 and the number of signers")]
+    InvalidThreshold,
+
+    #[msg("Signer is not part of the multisig")]
+    NotMultisigSigner,
+
+    #[msg("Not enough approvals to execute")]
+    NotEnoughApprovals,
+
+    #[msg("Transaction already executed")]
+    TransactionAlreadyExecuted,
+
+    #[msg("Signer set changed since the transaction was proposed")]
+    StaleTransaction,
 } 
\ No newline at end of file
@@ -52,7 +52,8 @@ describe("manual calls", () => {
         new anchor.BN(3600),
         true,
         new anchor.BN(500),
-        new anchor.BN(100)
+        new anchor.BN(100),
+        { fixedApy: {} }
       )
       .accounts({
         protocol: protocolAccount.publicKey,
@@ -115,6 +116,7 @@ describe("manual calls", () => {
         protocol: protocolAccount.publicKey,
         userTokenAccount: userTokenAccount,
         protocolTokenAccount: protocolTokenAccount,
+        depositMint: mint,
         user: wallet.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
@@ -133,8 +135,6 @@ describe("manual calls", () => {
       owner: protocolAccount.publicKey,
     });
 
-    const referrerVault = anchor.web3.Keypair.generate().publicKey;
-
     const tx = await program.methods
       .claim(new anchor.BN(1))
       .accounts({
@@ -142,7 +142,6 @@ describe("manual calls", () => {
         protocol: protocolAccount.publicKey,
         userInfo: userInfoAccount.publicKey,
         protocolVault: protocolVault,
-        referrerVault: referrerVault,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
       })
@@ -163,7 +162,8 @@ describe("manual calls", () => {
         new anchor.BN(3600), // lockPeriod
         true, // canSwap
         new anchor.BN(500), // rate
-        new anchor.BN(100) // apy
+        new anchor.BN(100), // apy
+        { fixedApy: {} } // accountingMode
       )
       .accounts({
         protocol: protocolAccount.publicKey,
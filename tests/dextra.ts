@@ -47,6 +47,9 @@ describe('dextra', () => {
   let poolPDA: PublicKey;
   let poolBump: number;
 
+  // The referrer's account in the pool, every claim of the referred user passes it
+  let referrerAccountPDA: PublicKey;
+
   // Define interfaces for our new structs
   interface ReferrerEntry {
     user: PublicKey;
@@ -141,7 +144,11 @@ describe('dextra', () => {
 
     // Find pool PDA
     [poolPDA, poolBump] = PublicKey.findProgramAddressSync(
-      [Buffer.from("pool"), protocolPDA.toBuffer()],
+      [Buffer.from("pool"), protocolPDA.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    [referrerAccountPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), poolPDA.toBuffer(), referrerKeypair.publicKey.toBuffer()],
       program.programId
     );
 
@@ -269,7 +276,8 @@ describe('dextra', () => {
         lockPeriod,
         canSwap,
         rate,
-        apy
+        apy,
+        { fixedApy: {} }
       )
       .accounts({
         protocol: protocolPDA,
@@ -319,6 +327,18 @@ describe('dextra', () => {
   it('Can deposit tokens with a referrer', async () => {
     // Create a new user info account for the user
     const userInfoAccount = Keypair.generate();
+
+    // Referred stake is tracked on the referrer's account, which anyone can open
+    await program.methods
+      .registerReferrer()
+      .accounts({
+        pool: poolPDA,
+        referrerAccount: referrerAccountPDA,
+        referrer: referrerKeypair.publicKey,
+        payer: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
     
     // Deposit with referral
     const poolId = new anchor.BN(0);
@@ -334,6 +354,8 @@ describe('dextra', () => {
         protocol: protocolPDA,
         userTokenAccount: userDepositTokenAccount,
         protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        referrerAccount: referrerAccountPDA,
         user: userKeypair.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
@@ -574,11 +596,12 @@ describe('dextra', () => {
         protocol: protocolPDA,
         userInfo: userInfoAccount.publicKey,
         protocolVault: protocolRewardTokenAccount,
-        referrerVault: referrerRewardTokenAccount,
+        rewardMint: rewardTokenMint,
         userTokenAccount: userRewardTokenAccount,
         user: userKeypair.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
+      .remainingAccounts([{ pubkey: referrerAccountPDA, isWritable: true, isSigner: false }])
       .signers([userKeypair])
       .rpc();
     
@@ -589,7 +612,7 @@ describe('dextra', () => {
     // Check that total claimed was updated
     assert.isTrue(updatedUserInfo.totalClaimed.toNumber() > 0, "Total claimed should be increased");
     
-    // Check if referrer received tokens
+    // Referral rewards accrue to the referrer's account, nothing is pushed here
     const referrerBalance = await provider.connection.getTokenAccountBalance(
       referrerRewardTokenAccount
     );
@@ -646,7 +669,9 @@ describe('dextra', () => {
         user: userKeypair.publicKey,
         pool: poolPDA,
         protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
         userTokenAccount: userDepositTokenAccount,
+        referrerAccount: referrerAccountPDA,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([userKeypair])
@@ -693,7 +718,11 @@ describe('dextra', () => {
     
     // Update the pool
     await program.methods
-      .updatePool(poolId, newMinimumDeposit, newLockPeriod, newCanSwap)
+      .updatePool(poolId, newMinimumDeposit, newLockPeriod, newCanSwap, {
+        maxPerUser: new anchor.BN(0),
+        maxTotalDeposits: new anchor.BN(0),
+        maxPerDeposit: new anchor.BN(0),
+      })
       .accounts({
         pool: poolPDA,
         protocol: protocolPDA,
@@ -840,11 +869,12 @@ describe('dextra', () => {
           protocol: protocolPDA,
           userInfo: userInfoAccount.publicKey,
           protocolVault: protocolRewardTokenAccount,
-          referrerVault: referrerRewardTokenAccount,
+          rewardMint: rewardTokenMint,
           userTokenAccount: userRewardTokenAccount,
           user: userKeypair.publicKey,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
+        .remainingAccounts([{ pubkey: referrerAccountPDA, isWritable: true, isSigner: false }])
         .signers([userKeypair])
         .rpc();
       
@@ -872,11 +902,12 @@ describe('dextra', () => {
         protocol: protocolPDA,
         userInfo: userInfoAccount.publicKey,
         protocolVault: protocolRewardTokenAccount,
-        referrerVault: referrerRewardTokenAccount,
+        rewardMint: rewardTokenMint,
         userTokenAccount: userRewardTokenAccount,
         user: userKeypair.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
+      .remainingAccounts([{ pubkey: referrerAccountPDA, isWritable: true, isSigner: false }])
       .signers([userKeypair])
       .rpc();
     
@@ -902,6 +933,8 @@ describe('dextra', () => {
         protocol: protocolPDA,
         userTokenAccount: userDepositTokenAccount,
         protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        referrerAccount: referrerAccountPDA,
         user: userKeypair.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
@@ -945,7 +978,9 @@ describe('dextra', () => {
           user: userKeypair.publicKey,
           pool: poolPDA,
           protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
           userTokenAccount: userDepositTokenAccount,
+          referrerAccount: referrerAccountPDA,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .signers([userKeypair])
@@ -976,7 +1011,9 @@ describe('dextra', () => {
         user: userKeypair.publicKey,
         pool: poolPDA,
         protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
         userTokenAccount: userDepositTokenAccount,
+        referrerAccount: referrerAccountPDA,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([userKeypair])
@@ -998,6 +1035,7 @@ describe('dextra', () => {
     let userTestTokenAccount: PublicKey;
     let receiverKeypair = Keypair.generate();
     let receiverTokenAccount: PublicKey;
+    let outflowLimitPDA: PublicKey;
 
     before(async () => {
       // Create a new test token mint
@@ -1056,6 +1094,21 @@ describe('dextra', () => {
       console.log("Protocol test token account:", protocolTestTokenAccount.toString());
       console.log("User test token account:", userTestTokenAccount.toString());
       console.log("Receiver test token account:", receiverTokenAccount.toString());
+
+      // Masscall only reaches allowlisted targets, allow SPL token Transfer (3)
+      await allowMasscallTarget(TOKEN_PROGRAM_ID, Buffer.from([3]));
+
+      // Protocol-signed transfers need an outflow limit for their mint
+      outflowLimitPDA = findOutflowLimitPDA(testTokenMint);
+      await program.methods
+        .setOutflowLimit(testTokenMint, new anchor.BN(1_000_000_000), new anchor.BN(86400))
+        .accounts({
+          protocol: protocolPDA,
+          outflowLimit: outflowLimitPDA,
+          authority: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
     });
 
     it('Should transfer tokens out using masscall (protocol -> receiver)', async () => {
@@ -1096,6 +1149,7 @@ describe('dextra', () => {
           // Protocol PDA - in the token instruction it's marked as a signer,
           // but in the client we mark it as NOT a signer since the program will sign for it
           { pubkey: protocolPDA, isWritable: false, isSigner: false },
+          { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
           // Add the Token Program to the remaining accounts
           { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
         ])
@@ -1226,6 +1280,7 @@ describe('dextra', () => {
           // Protocol PDA - in the token instruction it's marked as a signer,
           // but in the client we mark it as NOT a signer since the program will sign for it
           { pubkey: protocolPDA, isWritable: false, isSigner: false },
+          { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
           // Include the token program
           { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
         ])
@@ -1377,6 +1432,7 @@ describe('dextra', () => {
             // CRITICAL: Include the PDA as a non-signer in the client tx
             // The program will sign for it via CPI
             { pubkey: protocolPDA, isWritable: false, isSigner: false },
+            { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
             // Token Program
             { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
           ])
@@ -1453,6 +1509,7 @@ describe('dextra', () => {
             { pubkey: receiverTokenAccount, isWritable: true, isSigner: false },
             // owner (PDA)
             { pubkey: protocolPDA, isWritable: false, isSigner: false }, // Program will sign for this
+            { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
             // Token Program
             { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
           ])
@@ -1541,6 +1598,44 @@ describe('dextra', () => {
     return instruction;
   }
 
+  // Add a program/instruction prefix to the masscall allowlist, creating it on first use
+  async function allowMasscallTarget(programId: PublicKey, discriminator: Buffer) {
+    const [masscallAllowlist] = PublicKey.findProgramAddressSync(
+      [Buffer.from("masscall_allowlist")],
+      program.programId
+    );
+
+    const existing = await program.account.masscallAllowlist.fetchNullable(masscallAllowlist);
+    if (!existing) {
+      await program.methods
+        .initializeMasscallAllowlist()
+        .accounts({
+          protocol: protocolPDA,
+          masscallAllowlist,
+          authority: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    await program.methods
+      .addMasscallTarget(programId, discriminator)
+      .accounts({
+        protocol: protocolPDA,
+        masscallAllowlist,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+  }
+
+  // Outflow limit PDA of a mint
+  function findOutflowLimitPDA(mint: PublicKey) {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("outflow_limit"), mint.toBuffer()],
+      program.programId
+    )[0];
+  }
+
   // Create a masscall for SPL token transfer
   async function createSPLTokenTransferMasscall(
     receiverWallet: PublicKey,
@@ -1578,6 +1673,8 @@ describe('dextra', () => {
         { pubkey: owner, isWritable: false, isSigner: isPda ? false : true },
         // Token Program
         { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+        // Outflow limit of the mint, charged when the protocol PDA signs
+        ...(isPda ? [{ pubkey: findOutflowLimitPDA(mintKeypair.publicKey), isWritable: true, isSigner: false }] : []),
       ])
       .instruction();
 
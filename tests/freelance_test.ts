@@ -1,243 +1,295 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Freelance } from "../target/types/freelance";
-import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { Governance } from "../target/types/governance";
+import {
+  createMint,
+  getOrCreateAssociatedTokenAccount,
+  getAccount,
+  mintTo
+} from "@solana/spl-token";
+import { PublicKey, Keypair, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
 
 // Set custom provider
 anchor.setProvider(anchor.AnchorProvider.env());
 
 const program = anchor.workspace.Freelance as Program<Freelance>;
-
-const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+const governanceProgram = anchor.workspace.Governance as Program<Governance>;
 
 describe("freelance", () => {
-  // Static accounts, generated only once
-  const protocolAccount = anchor.web3.Keypair.generate();
-  const userInfoAccount = anchor.web3.Keypair.generate();
-  let dummyPool: anchor.web3.Keypair; // Declare dummyPool
-  let protocolVault: anchor.web3.Keypair;
-  let referrerVault: anchor.web3.Keypair;
-
   const provider = anchor.AnchorProvider.env();
   const wallet = (provider as anchor.AnchorProvider).wallet as anchor.Wallet;
+  const contractor = Keypair.generate();
+  const multisig = Keypair.generate();
+  const [multisigSigner] = PublicKey.findProgramAddressSync(
+    [Buffer.from("multisig_signer"), multisig.publicKey.toBuffer()],
+    governanceProgram.programId
+  );
+
+  let mint: PublicKey;
+  let clientTokenAccount: PublicKey;
+  let contractorTokenAccount: PublicKey;
+
+  const findEscrow = (escrowId: anchor.BN) => {
+    const [escrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), wallet.publicKey.toBuffer(), escrowId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow_vault"), escrow.toBuffer()],
+      program.programId
+    );
+    return { escrow, vault };
+  };
 
   before(async () => {
-    try {
-      // Airdrop to the wallet
-      await provider.connection.confirmTransaction(
-        await provider.connection.requestAirdrop(wallet.publicKey, 10 * anchor.web3.LAMPORTS_PER_SOL),
-        "confirmed"
-      );
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(contractor.publicKey, LAMPORTS_PER_SOL),
+      "confirmed"
+    );
 
-      // Initialize protocol and user info accounts
-      await program.methods
-        .initialize()
-        .accounts({
-          protocol: protocolAccount.publicKey, // Ensure this matches the expected account names in the claim method
-          userInfo: userInfoAccount.publicKey,
-          owner: wallet.publicKey,
-          systemProgram: anchor.web3.SystemProgram.programId,
-        })
-        .signers([protocolAccount, userInfoAccount])
-        .rpc();
+    // The arbiter is this multisig's signer PDA, a single approval executes
+    await governanceProgram.methods
+      .createMultisig([wallet.publicKey], 1)
+      .accounts({
+        multisig: multisig.publicKey,
+        multisigSigner,
+        payer: wallet.publicKey,
+      })
+      .signers([multisig])
+      .rpc();
 
-      console.log("Protocol account pubkey:", protocolAccount.publicKey.toString());
-      console.log("User info pubkey:", userInfoAccount.publicKey.toString());
+    await program.methods
+      .initializeConfig()
+      .accounts({
+        multisig: multisig.publicKey,
+        payer: wallet.publicKey,
+      })
+      .rpc();
 
-      const latestBlockhash = await provider.connection.getLatestBlockhash();
-      console.log("Connected to validator, blockhash:", latestBlockhash.blockhash);
-    } catch (e) {
-      console.error("Failed to connect to validator:", e);
-      throw e;
-    }
+    mint = await createMint(provider.connection, (wallet as any).payer, wallet.publicKey, null, 6);
+    clientTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      (wallet as any).payer,
+      mint,
+      wallet.publicKey
+    )).address;
+    contractorTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      (wallet as any).payer,
+      mint,
+      contractor.publicKey
+    )).address;
+
+    await mintTo(provider.connection, (wallet as any).payer, mint, clientTokenAccount, wallet.publicKey, 1_000_000_000);
   });
 
-  beforeEach(async () => {
-    dummyPool = anchor.web3.Keypair.generate(); // Generate dummyPool before each test
-    protocolVault = anchor.web3.Keypair.generate();
-    referrerVault = anchor.web3.Keypair.generate();
-
-    // Airdrop SOL to protocolVault with retry logic
-    let airdropSuccess = false;
-    let retryCount = 0;
-    while (!airdropSuccess && retryCount < 3) {
-      try {
-        await provider.connection.confirmTransaction(
-          await provider.connection.requestAirdrop(protocolVault.publicKey, anchor.web3.LAMPORTS_PER_SOL),
-          "confirmed"
-        );
-        airdropSuccess = true;
-      } catch (error) {
-        console.warn(`Airdrop to protocolVault failed (attempt ${retryCount + 1}):`, error);
-        retryCount++;
-        await sleep(1000); // Wait before retrying
-      }
-    }
+  it("should fund an escrow and release an approved milestone", async () => {
+    const escrowId = new anchor.BN(1);
+    const { escrow, vault } = findEscrow(escrowId);
+    const deadline = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
 
-    airdropSuccess = false;
-    retryCount = 0;
-    while (!airdropSuccess && retryCount < 3) {
-      try {
-        await provider.connection.confirmTransaction(
-          await provider.connection.requestAirdrop(referrerVault.publicKey, anchor.web3.LAMPORTS_PER_SOL),
-          "confirmed"
-        );
-        airdropSuccess = true;
-      } catch (error) {
-        console.warn(`Airdrop to referrerVault failed (attempt ${retryCount + 1}):`, error);
-        retryCount++;
-        await sleep(1000); // Wait before retrying
-      }
-    }
+    await program.methods
+      .createEscrow(escrowId, contractor.publicKey, [
+        { amount: new anchor.BN(100_000_000), deadline },
+        { amount: new anchor.BN(50_000_000), deadline },
+      ])
+      .accounts({
+        escrow,
+        vault,
+        mint,
+        clientTokenAccount,
+        multisig: multisig.publicKey,
+        client: wallet.publicKey,
+      })
+      .rpc();
+
+    const vaultBalance = Number((await getAccount(provider.connection, vault)).amount);
+    assert.equal(vaultBalance, 150_000_000);
 
-    // Initialize the pool account
-    const depositTokenKeypair = anchor.web3.Keypair.generate();
-    const rewardTokenKeypair = anchor.web3.Keypair.generate();
-    const depositToken = depositTokenKeypair.publicKey;
-    const rewardToken = rewardTokenKeypair.publicKey;
+    const escrowState = await program.account.escrow.fetch(escrow);
+    assert.ok(escrowState.arbiter.equals(multisigSigner), "Arbiter should be the multisig signer PDA");
+
+    await program.methods
+      .approveMilestone(0)
+      .accounts({
+        escrow,
+        vault,
+        contractorTokenAccount,
+        client: wallet.publicKey,
+      })
+      .rpc();
+
+    const contractorBalance = Number((await getAccount(provider.connection, contractorTokenAccount)).amount);
+    assert.equal(contractorBalance, 100_000_000);
+  });
+
+  it("should let the contractor dispute a milestone and block client approval paths", async () => {
+    const escrowId = new anchor.BN(1);
+    const { escrow, vault } = findEscrow(escrowId);
+
+    await program.methods
+      .disputeMilestone(1)
+      .accounts({
+        escrow,
+        party: contractor.publicKey,
+      })
+      .signers([contractor])
+      .rpc();
+
+    const escrowState = await program.account.escrow.fetch(escrow);
+    assert.ok(escrowState.milestones[1].status.disputed !== undefined, "Milestone should be disputed");
 
     try {
       await program.methods
-        .addPool(
-          new anchor.BN(1000), // minimumDeposit
-          new anchor.BN(3600), // lockPeriod
-          true, // canSwap
-          new anchor.BN(500), // rate
-          new anchor.BN(100) // apy
-        )
+        .refundMilestone(1)
         .accounts({
-          protocol: protocolAccount.publicKey,
-          pool: dummyPool.publicKey,
-          depositToken: depositToken,
-          rewardToken: rewardToken,
-          payer: wallet.publicKey,
-          systemProgram: anchor.web3.SystemProgram.programId,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          escrow,
+          vault,
+          clientTokenAccount,
+          client: wallet.publicKey,
         })
-        .signers([dummyPool, depositTokenKeypair, rewardTokenKeypair]) // Ensure dummyPool and wallet.payer are included as signers
         .rpc();
-      console.log("Pool added:", dummyPool.publicKey.toString());
+      assert.fail("Disputed milestones can only be resolved by the arbiter");
     } catch (error) {
-      console.error("addPool instruction failed:", error);
-      throw error; // Re-throw the error to fail the test
+      assert.include(error.message, "InvalidMilestoneStatus");
     }
   });
 
-  it("should initialize protocol and user info", async () => {
-    const newProtocol = anchor.web3.Keypair.generate();
-    const newUserInfo = anchor.web3.Keypair.generate();
-    const provider = anchor.getProvider();
-    const wallet = provider.wallet as anchor.Wallet;
+  it("should resolve a dispute through a multisig transaction", async () => {
+    const escrowId = new anchor.BN(1);
+    const { escrow, vault } = findEscrow(escrowId);
+    const contractorBefore = Number((await getAccount(provider.connection, contractorTokenAccount)).amount);
+    const clientBefore = Number((await getAccount(provider.connection, clientTokenAccount)).amount);
 
-    await program.methods
-      .initialize()
+    // Split the disputed 50 tokens 30 / 20
+    const innerIx = await program.methods
+      .resolveDispute(1, new anchor.BN(30_000_000))
       .accounts({
-        protocol: newProtocol.publicKey,
-        userInfo: newUserInfo.publicKey,
-        owner: wallet.publicKey,
-        systemProgram: anchor.web3.SystemProgram.programId,
+        escrow,
+        vault,
+        contractorTokenAccount,
+        clientTokenAccount,
+        arbiter: multisigSigner,
       })
-      .signers([newProtocol, newUserInfo])
-      .rpc();
+      .instruction();
 
-    const accountData = await program.account.userInfo.fetch(newUserInfo.publicKey);
-    console.log("Initialized userInfo account:", accountData);
-    if (!accountData) {
-      throw new Error("UserInfo account not initialized");
-    }
-  });
-
-  it("should add a new pool", async () => {
-    // Dummy public keys for tokens, replace with valid ones if available
-    const depositTokenKeypair = anchor.web3.Keypair.generate();
-    const rewardTokenKeypair = anchor.web3.Keypair.generate();
-    const depositToken = depositTokenKeypair.publicKey;
-    const rewardToken = rewardTokenKeypair.publicKey;
+    const multisigState = await governanceProgram.account.multisig.fetch(multisig.publicKey);
+    const [transaction] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("multisig_tx"),
+        multisig.publicKey.toBuffer(),
+        multisigState.transactionCount.toArrayLike(Buffer, "le", 8),
+      ],
+      governanceProgram.programId
+    );
 
-    await program.methods
-      .addPool(
-        new anchor.BN(1000), // minimumDeposit
-        new anchor.BN(3600), // lockPeriod
-        true, // canSwap
-        new anchor.BN(500), // rate
-        new anchor.BN(100) // apy
+    await governanceProgram.methods
+      .proposeTransaction(
+        program.programId,
+        innerIx.keys.map((key) => ({
+          pubkey: key.pubkey,
+          isSigner: false, // the multisig PDA is marked as signer on execution
+          isWritable: key.isWritable,
+        })),
+        innerIx.data
       )
       .accounts({
-        protocol: protocolAccount.publicKey,
-        pool: dummyPool.publicKey,
-        depositToken: depositToken,
-        rewardToken: rewardToken,
-        payer: wallet.publicKey,
-        systemProgram: anchor.web3.SystemProgram.programId,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        multisig: multisig.publicKey,
+        transaction,
+        proposer: wallet.publicKey,
       })
-      .signers([dummyPool, depositTokenKeypair, rewardTokenKeypair]) // Ensure dummyPool is included as a signer
       .rpc();
-    console.log("Pool added:", dummyPool.publicKey.toString());
-  });
 
-  it("should approve a user", async () => {
-    const dummyUser = anchor.web3.Keypair.generate().publicKey;
-    await program.methods.approve(
-      dummyUser,
-      1 // dummy approvalType
-    )
-    .accounts({
-      protocol: protocolAccount.publicKey,
-      authority: wallet.publicKey,
-      systemProgram: anchor.web3.SystemProgram.programId,
-    })
-    .rpc();
-    console.log("User approved:", dummyUser.toString());
+    await governanceProgram.methods
+      .executeTransaction()
+      .accounts({ multisig: multisig.publicKey, multisigSigner, transaction })
+      .remainingAccounts(
+        innerIx.keys
+          .map((key) => ({ pubkey: key.pubkey, isSigner: false, isWritable: key.isWritable }))
+          .concat([{ pubkey: program.programId, isSigner: false, isWritable: false }])
+      )
+      .rpc();
+
+    const contractorAfter = Number((await getAccount(provider.connection, contractorTokenAccount)).amount);
+    const clientAfter = Number((await getAccount(provider.connection, clientTokenAccount)).amount);
+    assert.equal(contractorAfter - contractorBefore, 30_000_000);
+    assert.equal(clientAfter - clientBefore, 20_000_000);
+
+    const escrowState = await program.account.escrow.fetch(escrow);
+    assert.ok(escrowState.milestones[1].status.resolved !== undefined, "Milestone should be resolved");
   });
 
-  it("should deposit funds", async () => {
-    // Dummy token accounts; in a real test these should be valid token accounts.
-    const userTokenAccountKeypair = anchor.web3.Keypair.generate();
-    const protocolTokenAccountKeypair = anchor.web3.Keypair.generate();
-    const userTokenAccount = userTokenAccountKeypair.publicKey;
-    const protocolTokenAccount = protocolTokenAccountKeypair.publicKey;
+  it("should not refund a milestone before its deadline", async () => {
+    const escrowId = new anchor.BN(2);
+    const { escrow, vault } = findEscrow(escrowId);
+    const deadline = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
 
     await program.methods
-      .deposit(
-        new anchor.BN(1), // poolId
-        new anchor.BN(2000), // amount
-        null // referrer (null for no referrer)
-      )
+      .createEscrow(escrowId, contractor.publicKey, [{ amount: new anchor.BN(10_000_000), deadline }])
       .accounts({
-        pool: dummyPool.publicKey,
-        userInfo: userInfoAccount.publicKey,
-        protocol: protocolAccount.publicKey,
-        userTokenAccount: userTokenAccount,
-        protocolTokenAccount: protocolTokenAccount,
-        user: wallet.publicKey,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        systemProgram: anchor.web3.SystemProgram.programId,
+        escrow,
+        vault,
+        mint,
+        clientTokenAccount,
+        multisig: multisig.publicKey,
+        client: wallet.publicKey,
       })
-      .signers([userInfoAccount, userTokenAccountKeypair]) // Add userInfoAccount to signers
       .rpc();
-    console.log("Deposit executed on pool:", dummyPool.publicKey.toString());
+
+    try {
+      await program.methods
+        .refundMilestone(0)
+        .accounts({
+          escrow,
+          vault,
+          clientTokenAccount,
+          client: wallet.publicKey,
+        })
+        .rpc();
+      assert.fail("Refund before the deadline should fail");
+    } catch (error) {
+      assert.include(error.message, "DeadlineNotReached");
+    }
   });
 
-  it("should claim rewards", async () => {
-    // Now execute the claim instruction
-    await program.methods
-      .claim(
-        new anchor.BN(1) // poolId
-      )
+  it("should reject an escrow arbitrated by a multisig other than the configured one", async () => {
+    const escrowId = new anchor.BN(3);
+    const { escrow, vault } = findEscrow(escrowId);
+    const deadline = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
+
+    // A 1-of-1 multisig of the client's own
+    const clientMultisig = Keypair.generate();
+    const [clientMultisigSigner] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig_signer"), clientMultisig.publicKey.toBuffer()],
+      governanceProgram.programId
+    );
+    await governanceProgram.methods
+      .createMultisig([wallet.publicKey], 1)
       .accounts({
-        pool: dummyPool.publicKey,
-        protocol: protocolAccount.publicKey,
-        userInfo: userInfoAccount.publicKey,
-        protocolVault: protocolVault.publicKey,
-        referrerVault: referrerVault.publicKey,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        systemProgram: anchor.web3.SystemProgram.programId,
+        multisig: clientMultisig.publicKey,
+        multisigSigner: clientMultisigSigner,
+        payer: wallet.publicKey,
       })
+      .signers([clientMultisig])
       .rpc();
-    console.log("Claim executed for pool:", dummyPool.publicKey.toString());
-  });
 
+    try {
+      await program.methods
+        .createEscrow(escrowId, contractor.publicKey, [{ amount: new anchor.BN(10_000_000), deadline }])
+        .accounts({
+          escrow,
+          vault,
+          mint,
+          clientTokenAccount,
+          multisig: clientMultisig.publicKey,
+          client: wallet.publicKey,
+        })
+        .rpc();
+      assert.fail("Only the configured multisig can arbitrate");
+    } catch (error) {
+      assert.include(error.message, "InvalidArbiter");
+    }
+  });
 });
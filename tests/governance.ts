@@ -0,0 +1,444 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Governance } from "../target/types/governance";
+import {
+  createMint,
+  getOrCreateAssociatedTokenAccount,
+  getAccount,
+  mintTo
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("Governance Treasury", () => {
+  // Configure the client to use the local cluster
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const governanceProgram = anchor.workspace.Governance as Program<Governance>;
+  const wallet = provider.wallet as anchor.Wallet;
+  const outsider = Keypair.generate();
+
+  let governanceAccount: Keypair;
+  let governanceAuthority: PublicKey;
+  let spendingLimit: PublicKey;
+  let mint: PublicKey;
+  let treasuryTokenAccount: PublicKey;
+  let recipientTokenAccount: PublicKey;
+
+  const LIMIT_PER_PERIOD = 100_000_000;
+
+  before(async () => {
+    const airdrop = await provider.connection.requestAirdrop(outsider.publicKey, LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(airdrop);
+
+    governanceAccount = Keypair.generate();
+    await governanceProgram.methods
+      .initialize()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([governanceAccount])
+      .rpc();
+
+    [governanceAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("governance"), governanceAccount.publicKey.toBuffer()],
+      governanceProgram.programId
+    );
+
+    mint = await createMint(provider.connection, (wallet as any).payer, wallet.publicKey, null, 6);
+
+    treasuryTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      (wallet as any).payer,
+      mint,
+      governanceAuthority,
+      true // allowOwnerOffCurve for PDA
+    )).address;
+
+    recipientTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      (wallet as any).payer,
+      mint,
+      outsider.publicKey
+    )).address;
+
+    await mintTo(
+      provider.connection,
+      (wallet as any).payer,
+      mint,
+      treasuryTokenAccount,
+      wallet.publicKey,
+      1_000_000_000
+    );
+
+    [spendingLimit] = PublicKey.findProgramAddressSync(
+      [Buffer.from("spending_limit"), governanceAccount.publicKey.toBuffer(), mint.toBuffer()],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .setSpendingLimit(new anchor.BN(LIMIT_PER_PERIOD), new anchor.BN(86400))
+      .accounts({
+        governance: governanceAccount.publicKey,
+        spendingLimit,
+        mint,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  });
+
+  it("Should reject send_tokens from a non-authority signer", async () => {
+    try {
+      await governanceProgram.methods
+        .sendTokens(new anchor.BN(1_000))
+        .accounts({
+          governance: governanceAccount.publicKey,
+          authority: outsider.publicKey,
+          governanceAuthority,
+          governanceTokenAccount: treasuryTokenAccount,
+          recipientTokenAccount,
+          spendingLimit,
+        })
+        .signers([outsider])
+        .rpc();
+      assert.fail("Non-authority should not be able to move treasury funds");
+    } catch (error) {
+      assert.include(error.message, "Unauthorized");
+    }
+  });
+
+  it("Should let the authority send tokens within the spending limit", async () => {
+    const amount = 40_000_000;
+    const before = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+
+    await governanceProgram.methods
+      .sendTokens(new anchor.BN(amount))
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+        governanceAuthority,
+        governanceTokenAccount: treasuryTokenAccount,
+        recipientTokenAccount,
+        spendingLimit,
+      })
+      .rpc();
+
+    const after = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+    assert.equal(after, before + amount);
+
+    const limit = await governanceProgram.account.spendingLimit.fetch(spendingLimit);
+    assert.equal(limit.spentInPeriod.toNumber(), amount);
+  });
+
+  it("Should refuse outflows above the remaining allowance", async () => {
+    try {
+      await governanceProgram.methods
+        .sendTokens(new anchor.BN(LIMIT_PER_PERIOD))
+        .accounts({
+          governance: governanceAccount.publicKey,
+          authority: wallet.publicKey,
+          governanceAuthority,
+          governanceTokenAccount: treasuryTokenAccount,
+          recipientTokenAccount,
+          spendingLimit,
+        })
+        .rpc();
+      assert.fail("Transfer above the spending limit should fail");
+    } catch (error) {
+      assert.include(error.message, "SpendingLimitExceeded");
+    }
+  });
+
+  it("Should execute an approved transfer proposal", async () => {
+    const amount = 10_000_000;
+    const state = await governanceProgram.account.governanceState.fetch(governanceAccount.publicKey);
+    const [proposal] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("proposal"),
+        governanceAccount.publicKey.toBuffer(),
+        state.proposalCount.toArrayLike(Buffer, "le", 8),
+      ],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .createProposal({
+        transfer: { mint, recipientTokenAccount, amount: new anchor.BN(amount) },
+      })
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        proposer: outsider.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([outsider])
+      .rpc();
+
+    await governanceProgram.methods
+      .approveProposal()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    const before = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+
+    // Execution is permissionless once the proposal is approved, the wallet only pays fees
+    await governanceProgram.methods
+      .executeTransferProposal()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        governanceAuthority,
+        governanceTokenAccount: treasuryTokenAccount,
+        recipientTokenAccount,
+        spendingLimit,
+      })
+      .rpc();
+
+    const after = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+    assert.equal(after, before + amount);
+
+    const executed = await governanceProgram.account.proposal.fetch(proposal);
+    assert.ok(executed.status.executed !== undefined, "Proposal should be marked executed");
+  });
+
+  it("Should stream treasury payments to a recipient", async () => {
+    const state = await governanceProgram.account.governanceState.fetch(governanceAccount.publicKey);
+    const [stream] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("stream"),
+        governanceAccount.publicKey.toBuffer(),
+        state.streamCount.toArrayLike(Buffer, "le", 8),
+      ],
+      governanceProgram.programId
+    );
+    const [streamVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("stream_vault"), stream.toBuffer()],
+      governanceProgram.programId
+    );
+
+    const now = Math.floor(Date.now() / 1000);
+    await governanceProgram.methods
+      .createStream(
+        outsider.publicKey,
+        new anchor.BN(1_000), // rate per second
+        new anchor.BN(now - 60), // already running
+        new anchor.BN(now + 3600),
+        true // cancellable
+      )
+      .accounts({
+        governance: governanceAccount.publicKey,
+        stream,
+        streamVault,
+        mint,
+        governanceAuthority,
+        governanceTokenAccount: treasuryTokenAccount,
+        spendingLimit,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    const before = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+    await governanceProgram.methods
+      .withdrawFromStream()
+      .accounts({
+        stream,
+        streamVault,
+        recipientTokenAccount,
+        recipient: outsider.publicKey,
+      })
+      .signers([outsider])
+      .rpc();
+    const after = Number((await getAccount(provider.connection, recipientTokenAccount)).amount);
+    assert.isAbove(after, before, "Recipient should receive the accrued amount");
+
+    const treasuryBefore = Number((await getAccount(provider.connection, treasuryTokenAccount)).amount);
+    await governanceProgram.methods
+      .cancelStream()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        stream,
+        streamVault,
+        governanceAuthority,
+        governanceTokenAccount: treasuryTokenAccount,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+    const treasuryAfter = Number((await getAccount(provider.connection, treasuryTokenAccount)).amount);
+    assert.isAbove(treasuryAfter, treasuryBefore, "Unaccrued remainder should return to the treasury");
+
+    const cancelled = await governanceProgram.account.paymentStream.fetch(stream);
+    assert.isTrue(cancelled.cancelled);
+  });
+
+  it("Should bound config values and update them through proposals", async () => {
+    const [config] = PublicKey.findProgramAddressSync(
+      [Buffer.from("config"), governanceAccount.publicKey.toBuffer()],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .initializeConfig()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        config,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    // max_apy may move between 1% and 50%
+    await governanceProgram.methods
+      .defineConfig("max_apy", { u64: [new anchor.BN(2000)] }, new anchor.BN(100), new anchor.BN(5000))
+      .accounts({
+        governance: governanceAccount.publicKey,
+        config,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    try {
+      await governanceProgram.methods
+        .setConfig("max_apy", { u64: [new anchor.BN(9000)] })
+        .accounts({
+          governance: governanceAccount.publicKey,
+          config,
+          authority: wallet.publicKey,
+        })
+        .rpc();
+      assert.fail("Value outside of the bounds should be rejected");
+    } catch (error) {
+      assert.include(error.message, "ConfigValueOutOfBounds");
+    }
+
+    const state = await governanceProgram.account.governanceState.fetch(governanceAccount.publicKey);
+    const [proposal] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("proposal"),
+        governanceAccount.publicKey.toBuffer(),
+        state.proposalCount.toArrayLike(Buffer, "le", 8),
+      ],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .createProposal({ setConfig: { key: "max_apy", value: { u64: [new anchor.BN(3000)] } } })
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        proposer: outsider.publicKey,
+      })
+      .signers([outsider])
+      .rpc();
+
+    await governanceProgram.methods
+      .approveProposal()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    await governanceProgram.methods
+      .executeConfigProposal()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        proposal,
+        config,
+      })
+      .rpc();
+
+    const registry = await governanceProgram.account.configRegistry.fetch(config);
+    const maxApy = registry.entries.find((entry) => entry.key === "max_apy");
+    assert.equal(maxApy.value.u64[0].toNumber(), 3000);
+  });
+
+  it("Should change the multisig signer set only with threshold approvals", async () => {
+    const signerA = Keypair.generate();
+    const signerB = Keypair.generate();
+    const multisig = Keypair.generate();
+    const [multisigSigner] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig_signer"), multisig.publicKey.toBuffer()],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .createMultisig([wallet.publicKey, signerA.publicKey, signerB.publicKey], 2)
+      .accounts({
+        multisig: multisig.publicKey,
+        multisigSigner,
+        payer: wallet.publicKey,
+      })
+      .signers([multisig])
+      .rpc();
+
+    // Drop signerB and require both remaining signers
+    const innerIx = await governanceProgram.methods
+      .setMultisigSigners([wallet.publicKey, signerA.publicKey], 2)
+      .accounts({
+        multisig: multisig.publicKey,
+        multisigSigner,
+      })
+      .instruction();
+
+    const [transaction] = PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig_tx"), multisig.publicKey.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      governanceProgram.programId
+    );
+
+    await governanceProgram.methods
+      .proposeTransaction(
+        governanceProgram.programId,
+        innerIx.keys.map((key) => ({
+          pubkey: key.pubkey,
+          isSigner: false, // the multisig PDA is marked as signer on execution
+          isWritable: key.isWritable,
+        })),
+        innerIx.data
+      )
+      .accounts({
+        multisig: multisig.publicKey,
+        transaction,
+        proposer: wallet.publicKey,
+      })
+      .rpc();
+
+    const remainingAccounts = innerIx.keys
+      .map((key) => ({ pubkey: key.pubkey, isSigner: false, isWritable: key.isWritable }))
+      .concat([{ pubkey: governanceProgram.programId, isSigner: false, isWritable: false }]);
+
+    try {
+      await governanceProgram.methods
+        .executeTransaction()
+        .accounts({ multisig: multisig.publicKey, multisigSigner, transaction })
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+      assert.fail("A single approval should not meet the threshold");
+    } catch (error) {
+      assert.include(error.message, "NotEnoughApprovals");
+    }
+
+    await governanceProgram.methods
+      .approveTransaction()
+      .accounts({ multisig: multisig.publicKey, transaction, signer: signerA.publicKey })
+      .signers([signerA])
+      .rpc();
+
+    await governanceProgram.methods
+      .executeTransaction()
+      .accounts({ multisig: multisig.publicKey, multisigSigner, transaction })
+      .remainingAccounts(remainingAccounts)
+      .rpc();
+
+    const updated = await governanceProgram.account.multisig.fetch(multisig.publicKey);
+    assert.equal(updated.signers.length, 2);
+    assert.equal(updated.signerSetSeqno, 1);
+  });
+});
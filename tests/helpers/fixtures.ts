@@ -0,0 +1,113 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Dextra } from "../../target/types/dextra";
+import {
+  TOKEN_PROGRAM_ID,
+  createMint,
+  getAccount,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+
+// Overrides for `addPool`, the defaults are a 1:1 rate at 10% APY without a lock
+export interface PoolOptions {
+  minimumDeposit?: number;
+  lockPeriod?: number;
+  canSwap?: boolean;
+  rate?: number;
+  apy?: number;
+  accountingMode?: any;
+  tokenProgram?: PublicKey;
+}
+
+// Setup shared by the suites that add pools of their own. They run against the protocol
+// dextra.ts initializes, a suite run on its own initializes it first.
+export const fixtures = () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Dextra as Program<Dextra>;
+  const wallet = provider.wallet as anchor.Wallet;
+  const payer = (wallet as any).payer;
+  const [protocolPDA] = PublicKey.findProgramAddressSync([Buffer.from("protocol")], program.programId);
+
+  const ensureProtocol = async () => {
+    const protocol = await program.account.protocolAccount.fetchNullable(protocolPDA);
+    if (protocol) {
+      return protocol;
+    }
+    const userInfo = Keypair.generate();
+    await program.methods
+      .initialize()
+      .accounts({
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        owner: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([userInfo])
+      .rpc();
+    return program.account.protocolAccount.fetch(protocolPDA);
+  };
+
+  // Id and address the next added pool gets
+  const nextPool = async () => {
+    const protocol = await ensureProtocol();
+    const poolId = protocol.poolCount;
+    const [poolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), protocolPDA.toBuffer(), poolId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    return { poolId, poolPDA };
+  };
+
+  const addPool = async (depositToken: PublicKey, rewardToken: PublicKey, options: PoolOptions = {}) => {
+    const { poolId, poolPDA } = await nextPool();
+    await program.methods
+      .addPool(
+        new anchor.BN(options.minimumDeposit ?? 1000),
+        new anchor.BN(options.lockPeriod ?? 0),
+        options.canSwap ?? false,
+        new anchor.BN(options.rate ?? 1_000_000),
+        new anchor.BN(options.apy ?? 1000),
+        options.accountingMode ?? { fixedApy: {} }
+      )
+      .accounts({
+        protocol: protocolPDA,
+        pool: poolPDA,
+        depositToken,
+        rewardToken,
+        solVault: null,
+        payer: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: options.tokenProgram ?? TOKEN_PROGRAM_ID,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    return { poolId, poolPDA };
+  };
+
+  const newMint = (decimals = 6, tokenProgram = TOKEN_PROGRAM_ID) =>
+    createMint(provider.connection, payer, wallet.publicKey, null, decimals, undefined, undefined, tokenProgram);
+
+  const ata = async (mint: PublicKey, owner: PublicKey, tokenProgram = TOKEN_PROGRAM_ID) =>
+    (await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, mint, owner, true, undefined, undefined, tokenProgram
+    )).address;
+
+  const mintTokens = (mint: PublicKey, destination: PublicKey, amount: number | bigint, tokenProgram = TOKEN_PROGRAM_ID) =>
+    mintTo(provider.connection, payer, mint, destination, wallet.publicKey, amount, [], undefined, tokenProgram);
+
+  const balance = async (tokenAccount: PublicKey, tokenProgram = TOKEN_PROGRAM_ID) =>
+    Number((await getAccount(provider.connection, tokenAccount, undefined, tokenProgram)).amount);
+
+  const airdrop = async (account: PublicKey, lamports = LAMPORTS_PER_SOL) => {
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(account, lamports),
+      "confirmed"
+    );
+  };
+
+  return { provider, program, wallet, payer, protocolPDA, ensureProtocol, nextPool, addPool, newMint, ata, mintTokens, balance, airdrop };
+};
@@ -0,0 +1,86 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("pool history", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  const apy = 1000;
+  const amount = new anchor.BN(100_000);
+
+  let poolPDA: PublicKey;
+  let poolHistoryPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+
+  const today = () => Math.floor(Date.now() / 1000 / 86400) * 86400;
+
+  const deposit = () =>
+    program.methods
+      .deposit(poolId, amount, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        poolHistory: poolHistoryPDA,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, await newMint(), { apy }));
+    [poolHistoryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_history"), poolPDA.toBuffer()],
+      program.programId
+    );
+    await airdrop(user.publicKey);
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    await mintTokens(depositTokenMint, userDepositTokenAccount, 1_000_000);
+  });
+
+  it("Snapshots the day the history starts", async () => {
+    await program.methods
+      .initializePoolHistory(poolId)
+      .accounts({
+        pool: poolPDA,
+        poolHistory: poolHistoryPDA,
+        payer: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const history = await program.account.poolHistory.fetch(poolHistoryPDA);
+    assert.ok(history.pool.equals(poolPDA));
+    assert.equal(history.snapshots.length, 1);
+    assert.equal(history.snapshots[0].day.toNumber(), today());
+    assert.equal(history.snapshots[0].totalDeposited.toNumber(), 0);
+    assert.equal(history.snapshots[0].effectiveApy.toNumber(), apy, "A 1:1 rate pays the pool's APY");
+  });
+
+  it("Writes a day only once", async () => {
+    await deposit();
+    await deposit();
+
+    const history = await program.account.poolHistory.fetch(poolHistoryPDA);
+    assert.equal(history.snapshots.length, 1);
+    assert.equal(history.nextIndex, 1);
+  });
+});
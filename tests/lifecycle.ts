@@ -0,0 +1,184 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("pool lifecycle", () => {
+  const { provider, program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  // Large enough to earn a reward within a few seconds
+  const amount = new anchor.BN(1_000_000_000_000);
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let rewardTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let protocolRewardTokenAccount: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+  let userRewardTokenAccount: PublicKey;
+
+  const now = () => Math.floor(Date.now() / 1000);
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  const updatePoolAccounts = () => ({
+    pool: poolPDA,
+    protocol: protocolPDA,
+    authority: wallet.publicKey,
+    configRegistry: null,
+    systemProgram: SystemProgram.programId,
+  });
+
+  const deposit = () =>
+    program.methods
+      .deposit(poolId, amount, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+  const closePool = () =>
+    program.methods
+      .closePool(poolId)
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        solVault: null,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    rewardTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, rewardTokenMint));
+    await airdrop(user.publicKey);
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    protocolRewardTokenAccount = await ata(rewardTokenMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    userRewardTokenAccount = await ata(rewardTokenMint, user.publicKey);
+    await mintTokens(depositTokenMint, userDepositTokenAccount, 2_000_000_000_000);
+    await mintTokens(rewardTokenMint, protocolRewardTokenAccount, 1_000_000);
+  });
+
+  it("Rejects deposits before the pool starts", async () => {
+    await program.methods
+      .setPoolSchedule(poolId, new anchor.BN(now() + 3600), new anchor.BN(now() + 7200))
+      .accounts(updatePoolAccounts())
+      .rpc();
+
+    try {
+      await deposit();
+      assert.fail("The pool has not started yet");
+    } catch (error) {
+      assert.include(error.message, "PoolNotOpen");
+    }
+
+    await program.methods
+      .setPoolSchedule(poolId, new anchor.BN(0), new anchor.BN(now() + 3600))
+      .accounts(updatePoolAccounts())
+      .rpc();
+    await deposit();
+  });
+
+  it("Sunsets the pool but keeps withdrawals open", async () => {
+    // Let the position earn something before rewards stop
+    await sleep(2000);
+    await program.methods.sunsetPool(poolId).accounts(updatePoolAccounts()).rpc();
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.ok(pool.closed);
+    assert.isAtMost(pool.endTs.toNumber(), now() + 1, "Rewards should stop at the sunset");
+
+    try {
+      await deposit();
+      assert.fail("A sunset pool takes no deposits");
+    } catch (error) {
+      assert.include(error.message, "PoolNotOpen");
+    }
+
+    try {
+      await closePool();
+      assert.fail("The pool still holds a position");
+    } catch (error) {
+      assert.include(error.message, "PoolNotEmpty");
+    }
+
+    await program.methods
+      .withdraw(poolId)
+      .accounts({
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        user: user.publicKey,
+        pool: poolPDA,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        userTokenAccount: userDepositTokenAccount,
+        solVault: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+  });
+
+  it("Keeps the pool open until every reward owed is claimed", async () => {
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.isAbove(pool.rewardsOwed.toNumber(), 0);
+
+    try {
+      await closePool();
+      assert.fail("The position's reward is still unclaimed");
+    } catch (error) {
+      assert.include(error.message, "RewardsOutstanding");
+    }
+
+    await program.methods
+      .claim(poolId)
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        protocolVault: protocolRewardTokenAccount,
+        referralConfig: null,
+        userTokenAccount: userRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        solVault: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.equal((await program.account.pool.fetch(poolPDA)).rewardsOwed.toNumber(), 0);
+  });
+
+  it("Closes an empty pool and reclaims its rent", async () => {
+    const rent = await provider.connection.getBalance(poolPDA);
+    const before = await provider.connection.getBalance(wallet.publicKey);
+
+    await closePool();
+
+    assert.isNull(await provider.connection.getAccountInfo(poolPDA));
+    // The rent comes back minus the transaction fee
+    assert.isAbove(await provider.connection.getBalance(wallet.publicKey), before + rent - LAMPORTS_PER_SOL / 100);
+  });
+});
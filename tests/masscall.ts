@@ -21,6 +21,42 @@ import {
 } from "@solana/web3.js";
 import { assert } from "chai";
 
+// Add a program/instruction prefix to Dextra's masscall allowlist, creating it on first use
+async function allowMasscallTarget(
+  dextraProgram: Program<Dextra>,
+  authority: PublicKey,
+  programId: PublicKey,
+  discriminator: Buffer
+) {
+  const [protocolPDA] = PublicKey.findProgramAddressSync([Buffer.from("protocol")], dextraProgram.programId);
+  const [masscallAllowlist] = PublicKey.findProgramAddressSync(
+    [Buffer.from("masscall_allowlist")],
+    dextraProgram.programId
+  );
+
+  const existing = await dextraProgram.account.masscallAllowlist.fetchNullable(masscallAllowlist);
+  if (!existing) {
+    await dextraProgram.methods
+      .initializeMasscallAllowlist()
+      .accounts({
+        protocol: protocolPDA,
+        masscallAllowlist,
+        authority,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  }
+
+  await dextraProgram.methods
+    .addMasscallTarget(programId, discriminator)
+    .accounts({
+      protocol: protocolPDA,
+      masscallAllowlist,
+      authority,
+    })
+    .rpc();
+}
+
 describe("Masscall Basic Test", () => {
   // Configure the client to use the local cluster
   const provider = anchor.AnchorProvider.env();
@@ -71,6 +107,35 @@ describe("Masscall Basic Test", () => {
       console.error("Failed to initialize governance:", error);
       throw error;
     }
+
+    // Masscall only reaches allowlisted targets
+    for (const method of ["incrementCounter", "willFail"]) {
+      const ix = await governanceProgram.methods[method]()
+        .accounts({ governance: governanceAccount.publicKey, authority: wallet.publicKey })
+        .instruction();
+      await allowMasscallTarget(dextraProgram, wallet.publicKey, governanceProgram.programId, ix.data.subarray(0, 8));
+    }
+  });
+
+  it("Should reject masscall targets that are not on the allowlist", async () => {
+    // SyncNative (17) is never allowlisted by these tests
+    try {
+      await dextraProgram.methods
+        .masscall(TOKEN_PROGRAM_ID, Buffer.from([17]))
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: governanceAccount.publicKey, isWritable: true, isSigner: false },
+        ])
+        .rpc();
+      assert.fail("Masscall to a non-allowlisted target should fail");
+    } catch (error) {
+      assert.include(error.message, "MasscallTargetNotAllowed");
+    }
   });
 
   it("Should fail with proper error when non-owner tries to call masscall", async () => {
@@ -334,6 +399,188 @@ describe("Masscall Basic Test", () => {
       throw error;
     }
   });
+
+  it("Should execute several instructions atomically with masscall_batch", async () => {
+    const initialState = await governanceProgram.account.governanceState.fetch(
+      governanceAccount.publicKey
+    );
+
+    const incrementIx = await governanceProgram.methods
+      .incrementCounter()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+      })
+      .instruction();
+
+    // Both entries use remaining accounts [0, 2): governance state and the authority
+    const entry = {
+      programId: governanceProgram.programId,
+      accountIndexRanges: [{ start: 0, end: 2 }],
+      data: incrementIx.data,
+      signWithProtocol: false,
+    };
+
+    await dextraProgram.methods
+      .masscallBatch([entry, entry])
+      .accounts({
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        governanceProgram: GOVERNANCE_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: governanceAccount.publicKey, isWritable: true, isSigner: false },
+        { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+        { pubkey: governanceProgram.programId, isWritable: false, isSigner: false },
+      ])
+      .rpc();
+
+    const finalState = await governanceProgram.account.governanceState.fetch(
+      governanceAccount.publicKey
+    );
+    assert.equal(
+      finalState.counter.toNumber(),
+      initialState.counter.toNumber() + 2,
+      "Both batch entries should have executed"
+    );
+  });
+
+  it("Should revert the whole batch when one entry fails", async () => {
+    const initialState = await governanceProgram.account.governanceState.fetch(
+      governanceAccount.publicKey
+    );
+
+    const incrementIx = await governanceProgram.methods
+      .incrementCounter()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+      })
+      .instruction();
+    const failIx = await governanceProgram.methods
+      .willFail()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+      })
+      .instruction();
+
+    try {
+      await dextraProgram.methods
+        .masscallBatch([
+          { programId: governanceProgram.programId, accountIndexRanges: [{ start: 0, end: 2 }], data: incrementIx.data, signWithProtocol: false },
+          { programId: governanceProgram.programId, accountIndexRanges: [{ start: 0, end: 2 }], data: failIx.data, signWithProtocol: false },
+        ])
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: governanceAccount.publicKey, isWritable: true, isSigner: false },
+          { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+          { pubkey: governanceProgram.programId, isWritable: false, isSigner: false },
+        ])
+        .rpc();
+      assert.fail("Batch with a failing entry should not succeed");
+    } catch (error) {
+      assert.include(error.message, "IntentionalFailure");
+    }
+
+    const finalState = await governanceProgram.account.governanceState.fetch(
+      governanceAccount.publicKey
+    );
+    assert.equal(
+      finalState.counter.toNumber(),
+      initialState.counter.toNumber(),
+      "Counter should be unchanged after a reverted batch"
+    );
+  });
+
+  it("Should pass the target's return data back and emit MasscallExecuted", async () => {
+    const initialState = await governanceProgram.account.governanceState.fetch(
+      governanceAccount.publicKey
+    );
+
+    const incrementIx = await governanceProgram.methods
+      .incrementCounter()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+      })
+      .instruction();
+
+    const simulation = await dextraProgram.methods
+      .masscall(governanceProgram.programId, incrementIx.data)
+      .accounts({
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        governanceProgram: GOVERNANCE_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: governanceAccount.publicKey, isWritable: true, isSigner: false },
+        { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+        { pubkey: governanceProgram.programId, isWritable: false, isSigner: false },
+      ])
+      .simulate();
+
+    // incrementCounter returns the new counter, Dextra wraps it in a MasscallResult
+    const expectedCounter = initialState.counter.toNumber() + 1;
+    const prefix = `Program return: ${dextraProgram.programId.toString()} `;
+    const returnLog = simulation.raw.find((log) => log.startsWith(prefix));
+    assert.ok(returnLog, "Masscall should set return data");
+    const result = dextraProgram.coder.types.decode("MasscallResult", Buffer.from(returnLog.slice(prefix.length), "base64"));
+    assert.ok(result.programId.equals(governanceProgram.programId));
+    assert.equal(Number(Buffer.from(result.returnData).readBigUInt64LE(0)), expectedCounter);
+
+    const executed = simulation.events.find((event) => event.name === "masscallExecuted");
+    assert.ok(executed, "MasscallExecuted should be emitted");
+    assert.ok(executed.data.programId.equals(governanceProgram.programId));
+    assert.equal(Buffer.from(executed.data.returnData).readBigUInt64LE(0), BigInt(expectedCounter));
+  });
+
+  it("Should surface the target's own error code", async () => {
+    const failIx = await governanceProgram.methods
+      .willFail()
+      .accounts({
+        governance: governanceAccount.publicKey,
+        authority: wallet.publicKey,
+      })
+      .instruction();
+
+    try {
+      await dextraProgram.methods
+        .masscall(governanceProgram.programId, failIx.data)
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: governanceAccount.publicKey, isWritable: true, isSigner: false },
+          { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+          { pubkey: governanceProgram.programId, isWritable: false, isSigner: false },
+        ])
+        .rpc();
+      assert.fail("willFail should fail through masscall");
+    } catch (error) {
+      // Both programs number from 6000, the failing program's id tells the codes apart
+      assert.include(error.message, "IntentionalFailure");
+      assert.notInclude(error.message, "CpiError");
+      assert.ok(
+        error.logs.some((log) => log.includes("Error Number: 6001")),
+        "The governance error code should be reported"
+      );
+      assert.ok(
+        error.logs.some((log) => log.includes(`Program ${governanceProgram.programId.toString()} failed`)),
+        "The failure should be attributed to governance"
+      );
+    }
+  });
 }); 
 
 // Add a new test suite for token transfers from Dextra to Governance
@@ -361,6 +608,7 @@ describe("Protocol-to-Governance Token Transfer Test", () => {
   let testTokenMint: PublicKey;
   let protocolTokenAccount: PublicKey;
   let governanceTokenAccount: PublicKey;
+  let outflowLimitPDA: PublicKey;
 
   before(async () => {
     console.log("\n=== Setting up Protocol-to-Governance Token Transfer Test ===");
@@ -428,6 +676,32 @@ describe("Protocol-to-Governance Token Transfer Test", () => {
       1000000000 // 1,000 tokens with 6 decimals
     );
     
+    // Masscall only reaches allowlisted targets
+    const incrementIx = await governanceProgram.methods
+      .incrementCounter()
+      .accounts({ governance: governanceAccount.publicKey, authority: wallet.publicKey })
+      .instruction();
+    await allowMasscallTarget(dextraProgram, wallet.publicKey, governanceProgram.programId, incrementIx.data.subarray(0, 8));
+    // Transfer (3), Approve (4), Revoke (5) and MintTo (7), which the token decoder must still refuse
+    for (const tag of [3, 4, 5, 7]) {
+      await allowMasscallTarget(dextraProgram, wallet.publicKey, TOKEN_PROGRAM_ID, Buffer.from([tag]));
+    }
+
+    // Protocol-signed outflows are charged against the mint's outflow limit
+    [outflowLimitPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("outflow_limit"), testTokenMint.toBuffer()],
+      dextraProgram.programId
+    );
+    await dextraProgram.methods
+      .setOutflowLimit(testTokenMint, new anchor.BN(500_000_000), new anchor.BN(86400))
+      .accounts({
+        protocol: protocolPDA,
+        outflowLimit: outflowLimitPDA,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
     // Verify initial balances
     const protocolBalance = Number((await getAccount(provider.connection, protocolTokenAccount)).amount);
     const governanceBalance = Number((await getAccount(provider.connection, governanceTokenAccount)).amount);
@@ -493,6 +767,7 @@ describe("Protocol-to-Governance Token Transfer Test", () => {
           { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
           { pubkey: governanceTokenAccount, isWritable: true, isSigner: false },
           { pubkey: protocolPDA, isWritable: false, isSigner: false },
+          { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
           { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
         ])
         .rpc();
@@ -570,4 +845,193 @@ describe("Protocol-to-Governance Token Transfer Test", () => {
       throw error;
     }
   });
-}); 
\ No newline at end of file
+
+  it("Should approve and revoke a delegate on the protocol token account", async () => {
+    const delegate = Keypair.generate();
+
+    const approveData = Buffer.alloc(9);
+    approveData.writeUInt8(4, 0);
+    approveData.writeBigUInt64LE(BigInt(1000), 1);
+
+    await dextraProgram.methods
+      .masscall(TOKEN_PROGRAM_ID, approveData)
+      .accounts({
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        governanceProgram: GOVERNANCE_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+        { pubkey: delegate.publicKey, isWritable: false, isSigner: false },
+        { pubkey: protocolPDA, isWritable: false, isSigner: false },
+        { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
+        { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+      ])
+      .rpc();
+
+    let tokenAccount = await getAccount(provider.connection, protocolTokenAccount);
+    assert.ok(tokenAccount.delegate.equals(delegate.publicKey), "Delegate should be set");
+    assert.equal(Number(tokenAccount.delegatedAmount), 1000);
+
+    await dextraProgram.methods
+      .masscall(TOKEN_PROGRAM_ID, Buffer.from([5]))
+      .accounts({
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        governanceProgram: GOVERNANCE_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+        { pubkey: protocolPDA, isWritable: false, isSigner: false },
+        { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+      ])
+      .rpc();
+
+    tokenAccount = await getAccount(provider.connection, protocolTokenAccount);
+    assert.isNull(tokenAccount.delegate, "Delegate should be revoked");
+  });
+
+  it("Should reject token instructions outside the supported set", async () => {
+    const mintToData = Buffer.alloc(9);
+    mintToData.writeUInt8(7, 0);
+    mintToData.writeBigUInt64LE(BigInt(1000), 1);
+
+    try {
+      await dextraProgram.methods
+        .masscall(TOKEN_PROGRAM_ID, mintToData)
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: testTokenMint, isWritable: true, isSigner: false },
+          { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+          { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+          { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+        ])
+        .rpc();
+      assert.fail("MintTo should not be routed through masscall");
+    } catch (error) {
+      assert.include(error.message, "UnsupportedTokenInstruction");
+    }
+  });
+
+  it("Should reject token instructions with the wrong accounts", async () => {
+    const transferData = Buffer.alloc(9);
+    transferData.writeUInt8(3, 0);
+    transferData.writeBigUInt64LE(BigInt(1000), 1);
+
+    try {
+      // Only source and destination are passed, the owner is missing
+      await dextraProgram.methods
+        .masscall(TOKEN_PROGRAM_ID, transferData)
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+          { pubkey: governanceTokenAccount, isWritable: true, isSigner: false },
+        ])
+        .rpc();
+      assert.fail("Transfer without an owner should fail");
+    } catch (error) {
+      assert.include(error.message, "InvalidTokenInstructionAccounts");
+    }
+  });
+
+  it("Should charge protocol transfers against the outflow limit", async () => {
+    const transferData = (amount: number) => {
+      const data = Buffer.alloc(9);
+      data.writeUInt8(3, 0);
+      data.writeBigUInt64LE(BigInt(amount), 1);
+      return data;
+    };
+    const transfer = (amount: number) =>
+      dextraProgram.methods
+        .masscall(TOKEN_PROGRAM_ID, transferData(amount))
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          governanceProgram: GOVERNANCE_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+          { pubkey: governanceTokenAccount, isWritable: true, isSigner: false },
+          { pubkey: protocolPDA, isWritable: false, isSigner: false },
+          { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
+          { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+        ])
+        .rpc();
+
+    const initialLimit = await dextraProgram.account.outflowLimit.fetch(outflowLimitPDA);
+    await transfer(1_000_000);
+    const updatedLimit = await dextraProgram.account.outflowLimit.fetch(outflowLimitPDA);
+    assert.equal(updatedLimit.spentInPeriod.toNumber(), initialLimit.spentInPeriod.toNumber() + 1_000_000);
+
+    // Whatever is left of the allowance plus one more token is refused
+    const remaining = updatedLimit.amountPerPeriod.toNumber() - updatedLimit.spentInPeriod.toNumber();
+    try {
+      await transfer(remaining + 1);
+      assert.fail("Transfer over the outflow limit should fail");
+    } catch (error) {
+      assert.include(error.message, "OutflowLimitExceeded");
+    }
+  });
+
+  it("Should report a protocol transfer in simulation without executing it", async () => {
+    const transferAmount = 2_000_000;
+    const transferData = Buffer.alloc(9);
+    transferData.writeUInt8(3, 0);
+    transferData.writeBigUInt64LE(BigInt(transferAmount), 1);
+
+    const initialProtocolBalance = Number((await getAccount(provider.connection, protocolTokenAccount)).amount);
+    const initialLimit = await dextraProgram.account.outflowLimit.fetch(outflowLimitPDA);
+
+    const simulation = await dextraProgram.methods
+      .simulateMasscall(TOKEN_PROGRAM_ID, transferData)
+      .accounts({
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        governanceProgram: GOVERNANCE_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: protocolTokenAccount, isWritable: true, isSigner: false },
+        { pubkey: governanceTokenAccount, isWritable: true, isSigner: false },
+        { pubkey: protocolPDA, isWritable: false, isSigner: false },
+        { pubkey: outflowLimitPDA, isWritable: true, isSigner: false },
+        { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+      ])
+      .simulate();
+
+    // The report comes back as the program's return data
+    const prefix = `Program return: ${dextraProgram.programId.toString()} `;
+    const returnLog = simulation.raw.find((log) => log.startsWith(prefix));
+    assert.ok(returnLog, "Simulation should set return data");
+    const report = dextraProgram.coder.types.decode("MasscallReport", Buffer.from(returnLog.slice(prefix.length), "base64"));
+
+    assert.ok(report.programId.equals(TOKEN_PROGRAM_ID));
+    assert.isTrue(report.signWithProtocol);
+    assert.equal(report.accountCount, 3);
+    assert.equal(report.tokenCall.transfer.amount.toNumber(), transferAmount);
+    assert.ok(report.outflow.mint.equals(testTokenMint));
+    assert.equal(
+      report.outflow.spentInPeriod.toNumber(),
+      initialLimit.spentInPeriod.toNumber() + transferAmount
+    );
+
+    // Nothing moved and nothing was charged
+    const finalProtocolBalance = Number((await getAccount(provider.connection, protocolTokenAccount)).amount);
+    const finalLimit = await dextraProgram.account.outflowLimit.fetch(outflowLimitPDA);
+    assert.equal(finalProtocolBalance, initialProtocolBalance);
+    assert.equal(finalLimit.spentInPeriod.toNumber(), initialLimit.spentInPeriod.toNumber());
+  });
+});
@@ -0,0 +1,159 @@
+import * as anchor from "@coral-xyz/anchor";
+import { PublicKey, Keypair, SystemProgram, Transaction, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("native SOL pools", () => {
+  const { provider, program, wallet, protocolPDA, nextPool, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  const minimumDeposit = new anchor.BN(LAMPORTS_PER_SOL / 100);
+
+  let poolPDA: PublicKey;
+  let solVaultPDA: PublicKey;
+  let poolId: anchor.BN;
+
+  const lamports = (account: PublicKey) => provider.connection.getBalance(account);
+
+  const addNativePool = (canSwap: boolean) =>
+    program.methods
+      .addPool(minimumDeposit, new anchor.BN(0), canSwap, new anchor.BN(1_000_000), new anchor.BN(1000), { fixedApy: {} })
+      .accounts({
+        protocol: protocolPDA,
+        pool: poolPDA,
+        depositToken: null,
+        rewardToken: null,
+        solVault: solVaultPDA,
+        payer: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+  const claim = () =>
+    program.methods
+      .claim(poolId)
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        protocolVault: null,
+        rewardMint: null,
+        referralConfig: null,
+        userTokenAccount: null,
+        solVault: solVaultPDA,
+        user: user.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+  const setPendingReward = (amount: number) =>
+    program.methods
+      .testHelperSetPendingReward(new anchor.BN(amount))
+      .accounts({
+        userInfo: userInfo.publicKey,
+        authority: wallet.publicKey,
+        protocol: protocolPDA,
+      })
+      .rpc();
+
+  before(async () => {
+    ({ poolId, poolPDA } = await nextPool());
+    [solVaultPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("sol_vault"), poolPDA.toBuffer()],
+      program.programId
+    );
+
+    await airdrop(user.publicKey, 2 * LAMPORTS_PER_SOL);
+  });
+
+  it("Rejects swaps on pools with a native side", async () => {
+    try {
+      await addNativePool(true);
+      assert.fail("Native SOL pools cannot swap");
+    } catch (error) {
+      assert.include(error.message, "SwapNotSupported");
+    }
+  });
+
+  it("Creates a pool whose deposit and reward are native SOL", async () => {
+    await addNativePool(false);
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.ok(pool.depositToken.equals(PublicKey.default), "Deposit side should be native SOL");
+    assert.ok(pool.rewardToken.equals(PublicKey.default), "Reward side should be native SOL");
+
+    const solVault = await program.account.solVault.fetch(solVaultPDA);
+    assert.ok(solVault.pool.equals(poolPDA));
+  });
+
+  it("Deposits SOL into the vault and withdraws it back", async () => {
+    const amount = minimumDeposit.muln(10);
+    const vaultBefore = await lamports(solVaultPDA);
+
+    await program.methods
+      .deposit(poolId, amount, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: null,
+        protocolTokenAccount: null,
+        depositMint: null,
+        solVault: solVaultPDA,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+    assert.equal(await lamports(solVaultPDA) - vaultBefore, amount.toNumber());
+
+    const userBefore = await lamports(user.publicKey);
+    await program.methods
+      .withdraw(poolId)
+      .accounts({
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        user: user.publicKey,
+        pool: poolPDA,
+        protocolTokenAccount: null,
+        depositMint: null,
+        userTokenAccount: null,
+        solVault: solVaultPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.equal(await lamports(user.publicKey) - userBefore, amount.toNumber());
+    assert.equal(await lamports(solVaultPDA), vaultBefore);
+  });
+
+  it("Pays SOL rewards but never below the vault's rent-exempt minimum", async () => {
+    // Everything above the rent-exempt minimum is available, one lamport more is not
+    const available = (await lamports(solVaultPDA)) -
+      (await provider.connection.getMinimumBalanceForRentExemption(program.account.solVault.size));
+    await setPendingReward(available + 1);
+    try {
+      await claim();
+      assert.fail("Claim should not drain the vault's rent");
+    } catch (error) {
+      assert.include(error.message, "InsufficientVaultBalance");
+    }
+
+    const reward = LAMPORTS_PER_SOL / 10;
+    await provider.sendAndConfirm(new Transaction().add(
+      SystemProgram.transfer({ fromPubkey: wallet.publicKey, toPubkey: solVaultPDA, lamports: reward })
+    ));
+    await setPendingReward(reward);
+
+    const userBefore = await lamports(user.publicKey);
+    await claim();
+    assert.equal(await lamports(user.publicKey) - userBefore, reward);
+  });
+});
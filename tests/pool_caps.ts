@@ -0,0 +1,102 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("deposit caps", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, airdrop } = fixtures();
+
+  const alice = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+  const bob = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+
+  const deposit = (user: any, amount: number) =>
+    program.methods
+      .deposit(poolId, new anchor.BN(amount), null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: user.userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: user.depositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.keypair, user.userInfo])
+      .rpc();
+
+  const expectError = async (promise: Promise<any>, errorName: string) => {
+    try {
+      await promise;
+      assert.fail(`Expected ${errorName}`);
+    } catch (error) {
+      assert.include(error.message, errorName);
+    }
+  };
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, await newMint()));
+
+    await program.methods
+      .updatePool(poolId, new anchor.BN(1000), new anchor.BN(0), false, {
+        maxPerUser: new anchor.BN(150_000),
+        maxTotalDeposits: new anchor.BN(250_000),
+        maxPerDeposit: new anchor.BN(100_000),
+      })
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        configRegistry: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+
+    for (const user of [alice, bob]) {
+      await airdrop(user.keypair.publicKey);
+      user.depositTokenAccount = await ata(depositTokenMint, user.keypair.publicKey);
+      await mintTokens(depositTokenMint, user.depositTokenAccount, 1_000_000);
+    }
+  });
+
+  it("Enforces the per-deposit maximum", async () => {
+    await expectError(deposit(alice, 100_001), "DepositAboveMaximum");
+    await deposit(alice, 100_000);
+  });
+
+  it("Enforces the per-user cap", async () => {
+    await expectError(deposit(alice, 60_000), "UserDepositCapExceeded");
+    await deposit(alice, 50_000);
+
+    // A fresh user info is still the same wallet
+    await expectError(deposit({ ...alice, userInfo: Keypair.generate() }, 10_000), "UserDepositCapExceeded");
+
+    const [walletPositionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("wallet_position"), poolPDA.toBuffer(), alice.keypair.publicKey.toBuffer()],
+      program.programId
+    );
+    const walletPosition = await program.account.walletPosition.fetch(walletPositionPDA);
+    assert.equal(walletPosition.amount.toNumber(), 150_000);
+  });
+
+  it("Enforces the pool's total cap", async () => {
+    await deposit(bob, 100_000);
+    await expectError(deposit(bob, 1000), "PoolDepositCapExceeded");
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.totalDeposited.toNumber(), 250_000);
+  });
+});
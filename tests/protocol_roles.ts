@@ -0,0 +1,36 @@
+import { PublicKey, Keypair } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("protocol roles", () => {
+  const { program, wallet, protocolPDA, ensureProtocol, airdrop } = fixtures();
+  const governance = Keypair.generate();
+
+  const setGovernance = (newGovernance: PublicKey, authority: Keypair | null) => {
+    const call = program.methods
+      .setGovernance(newGovernance)
+      .accounts({ protocol: protocolPDA, authority: authority ? authority.publicKey : wallet.publicKey });
+    return authority ? call.signers([authority]).rpc() : call.rpc();
+  };
+
+  before(async () => {
+    await ensureProtocol();
+    await airdrop(governance.publicKey);
+  });
+
+  it("Lets only the current governance hand governance over", async () => {
+    // The wallet is owner and governance after initialize
+    await setGovernance(governance.publicKey, null);
+
+    try {
+      await setGovernance(wallet.publicKey, null);
+      assert.fail("The owner alone must not take governance back");
+    } catch (error) {
+      assert.include(error.message, "Unauthorized");
+    }
+
+    await setGovernance(wallet.publicKey, governance);
+    const protocol = await program.account.protocolAccount.fetch(protocolPDA);
+    assert.ok(protocol.governance.equals(wallet.publicKey));
+  });
+});
@@ -0,0 +1,529 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Dextra } from "../target/types/dextra";
+import {
+  TOKEN_PROGRAM_ID,
+  getAccount,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+// Runs against the protocol and pool created by the dextra tests
+describe("referrals", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Dextra as Program<Dextra>;
+  const wallet = provider.wallet as anchor.Wallet;
+  const payer = (wallet as any).payer;
+
+  let protocolPDA: PublicKey;
+  let poolPDA: PublicKey;
+  let referralConfigPDA: PublicKey;
+  let depositTokenMint: PublicKey;
+  let rewardTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let protocolRewardTokenAccount: PublicKey;
+  let minimumDeposit: anchor.BN;
+
+  // top <- middle <- bottom, each referred by the one before
+  const top = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+  const middle = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+  const bottom = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+
+  const setupUser = async (user: any) => {
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.keypair.publicKey, LAMPORTS_PER_SOL),
+      "confirmed"
+    );
+    user.depositTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, depositTokenMint, user.keypair.publicKey
+    )).address;
+    user.rewardTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, rewardTokenMint, user.keypair.publicKey
+    )).address;
+    await mintTo(provider.connection, payer, depositTokenMint, user.depositTokenAccount, wallet.publicKey, 1_000_000_000_000);
+  };
+
+  const findReferrerAccount = (referrer: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), poolPDA.toBuffer(), referrer.toBuffer()],
+      program.programId
+    )[0];
+
+  const deposit = async (user: any, amount: anchor.BN, referrer: PublicKey | null, referrerAccount: PublicKey | null) => {
+    await program.methods
+      .deposit(new anchor.BN(0), amount, referrer)
+      .accounts({
+        pool: poolPDA,
+        userInfo: user.userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: user.depositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        referrerAccount,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.keypair, user.userInfo])
+      .rpc();
+  };
+
+  const balance = async (tokenAccount: PublicKey) =>
+    Number((await getAccount(provider.connection, tokenAccount)).amount);
+
+  before(async () => {
+    [protocolPDA] = PublicKey.findProgramAddressSync([Buffer.from("protocol")], program.programId);
+    [poolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), protocolPDA.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    [referralConfigPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("referral_config"), poolPDA.toBuffer()],
+      program.programId
+    );
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    depositTokenMint = pool.depositToken;
+    rewardTokenMint = pool.rewardToken;
+    minimumDeposit = pool.minimumDeposit;
+
+    protocolDepositTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, depositTokenMint, protocolPDA, true
+    )).address;
+    protocolRewardTokenAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, rewardTokenMint, protocolPDA, true
+    )).address;
+    await mintTo(provider.connection, payer, rewardTokenMint, protocolRewardTokenAccount, wallet.publicKey, 1_000_000_000_000);
+
+    for (const user of [top, middle, bottom]) {
+      await setupUser(user);
+    }
+
+    // Only registered referrers accrue rewards
+    for (const user of [top, middle]) {
+      user.referrerAccount = findReferrerAccount(user.keypair.publicKey);
+      await program.methods
+        .registerReferrer()
+        .accounts({
+          pool: poolPDA,
+          referrerAccount: user.referrerAccount,
+          referrer: user.keypair.publicKey,
+          payer: user.keypair.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user.keypair])
+        .rpc();
+    }
+  });
+
+  it("Rejects referral configs over the total cap", async () => {
+    try {
+      await program.methods
+        .setReferralConfig([new anchor.BN(1500), new anchor.BN(400)], [
+          { minReferredStake: new anchor.BN(1), bonusPercent: new anchor.BN(200) },
+        ])
+        .accounts({
+          protocol: protocolPDA,
+          pool: poolPDA,
+          referralConfig: referralConfigPDA,
+          authority: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("Levels plus the top tier bonus above the cap should be rejected");
+    } catch (error) {
+      assert.include(error.message, "ReferralPercentTooHigh");
+    }
+  });
+
+  it("Accrues every level of the referral chain on claim, with tier bonuses", async () => {
+    const tierStake = minimumDeposit.muln(10);
+
+    // 3% direct, 1% second level, +1% for referrers who brought in at least `tierStake`
+    await program.methods
+      .setReferralConfig([new anchor.BN(300), new anchor.BN(100)], [
+        { minReferredStake: tierStake, bonusPercent: new anchor.BN(100) },
+      ])
+      .accounts({
+        protocol: protocolPDA,
+        pool: poolPDA,
+        referralConfig: referralConfigPDA,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await deposit(top, minimumDeposit, null, null);
+    await deposit(middle, minimumDeposit, top.keypair.publicKey, top.referrerAccount);
+    await deposit(bottom, tierStake, middle.keypair.publicKey, middle.referrerAccount);
+
+    const middleStats = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    assert.equal(middleStats.refereeCount.toNumber(), 1);
+    assert.equal(middleStats.totalReferredStake.toString(), tierStake.toString());
+
+    const reward = 1_000_000;
+    await program.methods
+      .testHelperSetPendingReward(new anchor.BN(reward))
+      .accounts({
+        userInfo: bottom.userInfo.publicKey,
+        authority: wallet.publicKey,
+        protocol: protocolPDA,
+      })
+      .rpc();
+
+    await program.methods
+      .claim(new anchor.BN(0))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: bottom.userInfo.publicKey,
+        protocolVault: protocolRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        referralConfig: referralConfigPDA,
+        userTokenAccount: bottom.rewardTokenAccount,
+        user: bottom.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: middle.referrerAccount, isWritable: true, isSigner: false },
+        { pubkey: top.referrerAccount, isWritable: true, isSigner: false },
+      ])
+      .signers([bottom.keypair])
+      .rpc();
+
+    assert.equal(await balance(bottom.rewardTokenAccount), reward);
+
+    // middle reached the tier, top did not
+    const middleAccount = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    const topAccount = await program.account.referrerAccount.fetch(top.referrerAccount);
+    assert.equal(middleAccount.accrued.toNumber(), reward * 400 / 10000);
+    assert.equal(topAccount.accrued.toNumber(), reward * 100 / 10000);
+  });
+
+  it("Lets the referrer withdraw what accrued", async () => {
+    const accrued = (await program.account.referrerAccount.fetch(middle.referrerAccount)).accrued.toNumber();
+    const balanceBefore = await balance(middle.rewardTokenAccount);
+
+    await program.methods
+      .claimReferralRewards(new anchor.BN(0))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        referrerAccount: middle.referrerAccount,
+        protocolVault: protocolRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        referrerTokenAccount: middle.rewardTokenAccount,
+        referrer: middle.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([middle.keypair])
+      .rpc();
+
+    assert.equal(await balance(middle.rewardTokenAccount) - balanceBefore, accrued);
+    const middleAccount = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    assert.equal(middleAccount.accrued.toNumber(), 0);
+    assert.equal(middleAccount.totalEarned.toNumber(), accrued);
+  });
+
+  it("Rejects a claim that leaves out a linked referrer's account", async () => {
+    await program.methods
+      .testHelperSetPendingReward(new anchor.BN(1_000_000))
+      .accounts({
+        userInfo: bottom.userInfo.publicKey,
+        authority: wallet.publicKey,
+        protocol: protocolPDA,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .claim(new anchor.BN(0))
+        .accounts({
+          pool: poolPDA,
+          protocol: protocolPDA,
+          userInfo: bottom.userInfo.publicKey,
+          protocolVault: protocolRewardTokenAccount,
+          rewardMint: rewardTokenMint,
+          referralConfig: referralConfigPDA,
+          userTokenAccount: bottom.rewardTokenAccount,
+          user: bottom.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([{ pubkey: middle.referrerAccount, isWritable: true, isSigner: false }])
+        .signers([bottom.keypair])
+        .rpc();
+      assert.fail("top's share must not be dropped");
+    } catch (error) {
+      assert.include(error.message, "ReferrerAccountRequired");
+    }
+  });
+
+  it("Rejects a claim that leaves out the pool's referral config", async () => {
+    try {
+      await program.methods
+        .claim(new anchor.BN(0))
+        .accounts({
+          pool: poolPDA,
+          protocol: protocolPDA,
+          userInfo: bottom.userInfo.publicKey,
+          protocolVault: protocolRewardTokenAccount,
+          rewardMint: rewardTokenMint,
+          referralConfig: null,
+          userTokenAccount: bottom.rewardTokenAccount,
+          user: bottom.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([
+          { pubkey: middle.referrerAccount, isWritable: true, isSigner: false },
+          { pubkey: top.referrerAccount, isWritable: true, isSigner: false },
+        ])
+        .signers([bottom.keypair])
+        .rpc();
+      assert.fail("The protocol default must not replace the pool's levels");
+    } catch (error) {
+      assert.include(error.message, "ReferralConfigRequired");
+    }
+  });
+
+  it("Rejects a referrer account that is not the depositor's referrer", async () => {
+    try {
+      await deposit(bottom, minimumDeposit, null, top.referrerAccount);
+      assert.fail("Deposit should not credit someone else's referrer account");
+    } catch (error) {
+      assert.include(error.message, "InvalidReferrerAccount");
+    }
+  });
+
+  it("Rejects self-referral and direct referral cycles", async () => {
+    const loner = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+    await setupUser(loner);
+    try {
+      await deposit(loner, minimumDeposit, loner.keypair.publicKey, null);
+      assert.fail("Self-referral should be rejected");
+    } catch (error) {
+      assert.include(error.message, "SelfReferral");
+    }
+
+    // middle is referred by top, so top cannot be referred by middle
+    try {
+      await deposit(top, minimumDeposit, middle.keypair.publicKey, null);
+      assert.fail("Referral cycle should be rejected");
+    } catch (error) {
+      assert.include(error.message, "ReferralCycle");
+    }
+  });
+
+  it("Resolves the referrer from a referral code", async () => {
+    const code = "top-ref";
+    const [referralCodePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("referral_code"), Buffer.from(code)],
+      program.programId
+    );
+    const registerCode = (user: any, code: string, referralCode: PublicKey) =>
+      program.methods
+        .registerReferralCode(code)
+        .accounts({
+          referralCode,
+          referrer: user.keypair.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user.keypair])
+        .rpc();
+
+    await registerCode(top, code, referralCodePDA);
+
+    // Codes are unique, a second registration of the same code fails
+    try {
+      await registerCode(middle, code, referralCodePDA);
+      assert.fail("A taken code should not be registered again");
+    } catch (error) {
+      assert.notInclude(error.message, "A taken code should not be registered again");
+    }
+
+    const invalidCode = "bad code";
+    try {
+      await registerCode(middle, invalidCode, PublicKey.findProgramAddressSync(
+        [Buffer.from("referral_code"), Buffer.from(invalidCode)],
+        program.programId
+      )[0]);
+      assert.fail("Invalid code should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidReferralCode");
+    }
+
+    const coded = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+    await setupUser(coded);
+    const topBefore = await program.account.referrerAccount.fetch(top.referrerAccount);
+
+    await program.methods
+      .deposit(new anchor.BN(0), minimumDeposit, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: coded.userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: coded.depositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        referralCode: referralCodePDA,
+        referrerAccount: top.referrerAccount,
+        user: coded.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([coded.keypair, coded.userInfo])
+      .rpc();
+
+    const userInfo = await program.account.userInfo.fetch(coded.userInfo.publicKey);
+    assert.ok(userInfo.referrer.equals(top.keypair.publicKey), "Code should resolve to its owner");
+    const topAfter = await program.account.referrerAccount.fetch(top.referrerAccount);
+    assert.equal(topAfter.refereeCount.toNumber(), topBefore.refereeCount.toNumber() + 1);
+  });
+
+  it("Keeps referral shares in the vault while the referee is not eligible", async () => {
+    const setEligibility = (minStake: anchor.BN, minDuration: anchor.BN) =>
+      program.methods
+        .setReferralEligibility(minStake, minDuration)
+        .accounts({
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+        })
+        .rpc();
+
+    // Far more stake than bottom has
+    await setEligibility(new anchor.BN("1000000000000000"), new anchor.BN(0));
+
+    const reward = 1_000_000;
+    await program.methods
+      .testHelperSetPendingReward(new anchor.BN(reward))
+      .accounts({
+        userInfo: bottom.userInfo.publicKey,
+        authority: wallet.publicKey,
+        protocol: protocolPDA,
+      })
+      .rpc();
+
+    const middleBefore = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    const vaultBefore = await balance(protocolRewardTokenAccount);
+
+    await program.methods
+      .claim(new anchor.BN(0))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: bottom.userInfo.publicKey,
+        protocolVault: protocolRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        referralConfig: referralConfigPDA,
+        userTokenAccount: bottom.rewardTokenAccount,
+        user: bottom.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: middle.referrerAccount, isWritable: true, isSigner: false },
+        { pubkey: top.referrerAccount, isWritable: true, isSigner: false },
+      ])
+      .signers([bottom.keypair])
+      .rpc();
+
+    // The referee is still paid, only the referral shares are withheld
+    assert.equal(vaultBefore - await balance(protocolRewardTokenAccount), reward);
+    const middleAfter = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    assert.equal(middleAfter.accrued.toNumber(), middleBefore.accrued.toNumber());
+
+    await setEligibility(new anchor.BN(0), new anchor.BN(0));
+  });
+
+  it("Releases the referred stake when the referee withdraws", async () => {
+    const withdraw = (referrerAccount: PublicKey | null) =>
+      program.methods
+        .withdraw(new anchor.BN(0))
+        .accounts({
+          protocol: protocolPDA,
+          userInfo: bottom.userInfo.publicKey,
+          user: bottom.keypair.publicKey,
+          pool: poolPDA,
+          protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
+          userTokenAccount: bottom.depositTokenAccount,
+          referrerAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([bottom.keypair])
+        .rpc();
+
+    await program.methods
+      .testHelperSetDepositUnlocked(new anchor.BN(0))
+      .accounts({
+        userInfo: bottom.userInfo.publicKey,
+        authority: wallet.publicKey,
+        protocol: protocolPDA,
+      })
+      .rpc();
+
+    try {
+      await withdraw(null);
+      assert.fail("The referrer's stats must be updated");
+    } catch (error) {
+      assert.include(error.message, "ReferrerAccountRequired");
+    }
+
+    const staked = (await program.account.userInfo.fetch(bottom.userInfo.publicKey)).amount;
+    const before = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    await withdraw(middle.referrerAccount);
+
+    const after = await program.account.referrerAccount.fetch(middle.referrerAccount);
+    assert.equal(
+      after.totalReferredStake.toString(),
+      before.totalReferredStake.sub(staked).toString()
+    );
+  });
+
+  it("Counts a referee once in every pool they deposit in", async () => {
+    const { addPool } = fixtures();
+    const { poolId: otherPoolId, poolPDA: otherPoolPDA } = await addPool(depositTokenMint, rewardTokenMint);
+    const [otherReferrerAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), otherPoolPDA.toBuffer(), middle.keypair.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .registerReferrer()
+      .accounts({
+        pool: otherPoolPDA,
+        referrerAccount: otherReferrerAccount,
+        referrer: middle.keypair.publicKey,
+        payer: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    // bottom was linked to middle by an earlier deposit in pool 0, a second user info doesn't count again
+    for (let i = 0; i < 2; i++) {
+      const userInfo = Keypair.generate();
+      await program.methods
+        .deposit(otherPoolId, minimumDeposit, null)
+        .accounts({
+          pool: otherPoolPDA,
+          userInfo: userInfo.publicKey,
+          protocol: protocolPDA,
+          userTokenAccount: bottom.depositTokenAccount,
+          protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
+          referrerAccount: otherReferrerAccount,
+          user: bottom.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([bottom.keypair, userInfo])
+        .rpc();
+    }
+
+    const otherAccount = await program.account.referrerAccount.fetch(otherReferrerAccount);
+    assert.equal(otherAccount.refereeCount.toNumber(), 1);
+    assert.equal(otherAccount.totalReferredStake.toString(), minimumDeposit.muln(2).toString());
+  });
+});
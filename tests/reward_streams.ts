@@ -0,0 +1,195 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("reward streams", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, balance, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let rewardTokenMint: PublicKey;
+  let streamMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let protocolRewardTokenAccount: PublicKey;
+  let streamVault: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+  let userRewardTokenAccount: PublicKey;
+  let userStreamTokenAccount: PublicKey;
+
+  const stake = 1_000_000_000_000;
+  const rate = 1_000_000;
+  const baseApy = 1000;
+  const streamApy = 500;
+
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  // Mirrors the program's accrual: one yield per UTC day, each rounded down
+  const accrued = (amount: number, from: number, to: number, apy: number, rate: number) => {
+    let total = BigInt(0);
+    for (let day = Math.floor(from / 86400) * 86400; day < to; day += 86400) {
+      const seconds = Math.min(day + 86400, to) - Math.max(day, from);
+      const yieldAmount = (BigInt(amount) * BigInt(seconds) * BigInt(apy)) / BigInt(100 * 365 * 86400 * 100);
+      total += (yieldAmount * BigInt(rate)) / BigInt(1_000_000);
+    }
+    return Number(total);
+  };
+
+  const addRewardStream = (mint: PublicKey, vault: PublicKey) =>
+    program.methods
+      .addRewardStream(poolId, new anchor.BN(rate), new anchor.BN(streamApy))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        rewardMint: mint,
+        rewardVault: vault,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const deposit = (amount: number) =>
+    program.methods
+      .deposit(poolId, new anchor.BN(amount), null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+  // Each stream passes its mint, vault, the user's account and the mint's token program
+  const claim = (streams: { mint: PublicKey, vault: PublicKey, userAccount: PublicKey, tokenProgram: PublicKey }[]) =>
+    program.methods
+      .claim(poolId)
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        protocolVault: protocolRewardTokenAccount,
+        referralConfig: null,
+        userTokenAccount: userRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        solVault: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(streams.flatMap((stream) => [
+        { pubkey: stream.mint, isWritable: false, isSigner: false },
+        { pubkey: stream.vault, isWritable: true, isSigner: false },
+        { pubkey: stream.userAccount, isWritable: true, isSigner: false },
+        { pubkey: stream.tokenProgram, isWritable: false, isSigner: false },
+      ]))
+      .signers([user]);
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    rewardTokenMint = await newMint();
+    streamMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, rewardTokenMint, { rate, apy: baseApy }));
+    await airdrop(user.publicKey);
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    protocolRewardTokenAccount = await ata(rewardTokenMint, protocolPDA);
+    streamVault = await ata(streamMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    userRewardTokenAccount = await ata(rewardTokenMint, user.publicKey);
+    userStreamTokenAccount = await ata(streamMint, user.publicKey);
+
+    await mintTokens(depositTokenMint, userDepositTokenAccount, 2 * stake);
+    await mintTokens(rewardTokenMint, protocolRewardTokenAccount, 1_000_000);
+    await mintTokens(streamMint, streamVault, 1_000_000);
+  });
+
+  it("Adds a reward stream once per mint", async () => {
+    await addRewardStream(streamMint, streamVault);
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.rewardStreams.length, 1);
+    assert.ok(pool.rewardStreams[0].mint.equals(streamMint));
+    assert.ok(pool.rewardStreams[0].vault.equals(streamVault));
+    assert.equal(pool.rewardStreams[0].totalPaid.toNumber(), 0);
+
+    try {
+      await addRewardStream(streamMint, streamVault);
+      assert.fail("The same mint should not be streamed twice");
+    } catch (error) {
+      assert.include(error.message, "DuplicateRewardStream");
+    }
+  });
+
+  it("Pays the base reward and every stream on claim", async () => {
+    const lastClaimed = async () =>
+      (await program.account.userInfo.fetch(userInfo.publicKey)).lastClaimed.toNumber();
+
+    // The second deposit accrues what the first one earned in between
+    await deposit(stake);
+    const from = await lastClaimed();
+    await sleep(2000);
+    await deposit(1000);
+    const to = await lastClaimed();
+
+    const baseReward = accrued(stake, from, to, baseApy, rate);
+    const streamReward = accrued(stake, from, to, streamApy, rate);
+    assert.isAbove(streamReward, 0);
+
+    const streamClaim = claim([
+      { mint: streamMint, vault: streamVault, userAccount: userStreamTokenAccount, tokenProgram: TOKEN_PROGRAM_ID },
+    ]);
+
+    const simulation = await streamClaim.simulate();
+    const claimEvent = simulation.events.find((event) => event.name === "claimEvent");
+    assert.ok(claimEvent, "ClaimEvent should be emitted");
+    assert.deepEqual(
+      claimEvent.data.rewards.map((reward: any) => [reward.mint.toBase58(), reward.amount.toNumber()]),
+      [[rewardTokenMint.toBase58(), baseReward], [streamMint.toBase58(), streamReward]]
+    );
+
+    await streamClaim.rpc();
+
+    assert.equal(await balance(userRewardTokenAccount), baseReward);
+    assert.equal(await balance(userStreamTokenAccount), streamReward);
+    const info = await program.account.userInfo.fetch(userInfo.publicKey);
+    assert.deepEqual(info.pendingStreamRewards.map((amount) => amount.toNumber()), [0]);
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.rewardStreams[0].totalPaid.toNumber(), streamReward);
+  });
+
+  it("Pays a Token-2022 stream on a pool of legacy tokens", async () => {
+    const mint2022 = await newMint(6, TOKEN_2022_PROGRAM_ID);
+    const vault2022 = await ata(mint2022, protocolPDA, TOKEN_2022_PROGRAM_ID);
+    const userAccount2022 = await ata(mint2022, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    await mintTokens(mint2022, vault2022, 1_000_000, TOKEN_2022_PROGRAM_ID);
+    await addRewardStream(mint2022, vault2022);
+
+    await sleep(2000);
+    await deposit(1000);
+    const pending = (await program.account.userInfo.fetch(userInfo.publicKey)).pendingStreamRewards[1].toNumber();
+    assert.isAbove(pending, 0);
+
+    await claim([
+      { mint: streamMint, vault: streamVault, userAccount: userStreamTokenAccount, tokenProgram: TOKEN_PROGRAM_ID },
+      { mint: mint2022, vault: vault2022, userAccount: userAccount2022, tokenProgram: TOKEN_2022_PROGRAM_ID },
+    ]).rpc();
+
+    assert.equal(await balance(userAccount2022, TOKEN_2022_PROGRAM_ID), pending);
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.rewardStreams[1].totalPaid.toNumber(), pending);
+  });
+});
@@ -0,0 +1,116 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("shared-emissions pools", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, airdrop } = fixtures();
+
+  const emissionPerSecond = 1_000;
+  const stake = new anchor.BN(1_000_000);
+
+  const alice = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+  const bob = { keypair: Keypair.generate(), userInfo: Keypair.generate() } as any;
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  const deposit = (user: any, amount: anchor.BN) =>
+    program.methods
+      .deposit(poolId, amount, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: user.userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: user.depositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.keypair, user.userInfo])
+      .rpc();
+
+  const lastRewardTimestamp = async () =>
+    (await program.account.pool.fetch(poolPDA)).lastRewardTimestamp.toNumber();
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, await newMint(), {
+      apy: 0,
+      accountingMode: { sharedEmissions: {} },
+    }));
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+
+    for (const user of [alice, bob]) {
+      await airdrop(user.keypair.publicKey);
+      user.depositTokenAccount = await ata(depositTokenMint, user.keypair.publicKey);
+      await mintTokens(depositTokenMint, user.depositTokenAccount, 10_000_000);
+    }
+  });
+
+  it("Only lets shared-emissions pools set an emission rate", async () => {
+    const [fixedPoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), protocolPDA.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    try {
+      await program.methods
+        .setEmissionRate(new anchor.BN(0), new anchor.BN(emissionPerSecond))
+        .accounts({
+          pool: fixedPoolPDA,
+          protocol: protocolPDA,
+          authority: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("Fixed-APY pools have no emission rate");
+    } catch (error) {
+      assert.include(error.message, "InvalidAccountingMode");
+    }
+
+    await program.methods
+      .setEmissionRate(poolId, new anchor.BN(emissionPerSecond))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.ok(pool.accountingMode.sharedEmissions !== undefined);
+    assert.equal(pool.emissionPerSecond.toNumber(), emissionPerSecond);
+  });
+
+  it("Splits emissions pro-rata between depositors", async () => {
+    await deposit(alice, stake);
+    const aliceJoined = await lastRewardTimestamp();
+
+    // Alice earns everything until Bob joins with the same stake
+    await sleep(2000);
+    await deposit(bob, stake);
+    const bobJoined = await lastRewardTimestamp();
+
+    await sleep(2000);
+    await deposit(alice, new anchor.BN(1000));
+    const settled = await lastRewardTimestamp();
+
+    const aliceInfo = await program.account.userInfo.fetch(alice.userInfo.publicKey);
+    const expected = emissionPerSecond * (bobJoined - aliceJoined) + emissionPerSecond * (settled - bobJoined) / 2;
+    assert.equal(aliceInfo.pendingReward.toNumber(), expected);
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.totalDeposited.toString(), stake.muln(2).addn(1000).toString());
+  });
+});
@@ -0,0 +1,109 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("reward solvency", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  // 10% a year at a 1:1 rate, so a year of rewards is a tenth of the deposits
+  const apy = 1000;
+  const budget = 50_000;
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let rewardTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let protocolRewardTokenAccount: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+  let funderRewardTokenAccount: PublicKey;
+
+  const deposit = (amount: number) =>
+    program.methods
+      .deposit(poolId, new anchor.BN(amount), null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    rewardTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, rewardTokenMint, { apy }));
+    await airdrop(user.publicKey);
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    protocolRewardTokenAccount = await ata(rewardTokenMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    funderRewardTokenAccount = await ata(rewardTokenMint, wallet.publicKey);
+
+    await mintTokens(depositTokenMint, userDepositTokenAccount, 10_000_000);
+    await mintTokens(rewardTokenMint, funderRewardTokenAccount, 1_000_000);
+  });
+
+  it("Funds the reward budget", async () => {
+    await program.methods
+      .fundRewards(poolId, new anchor.BN(budget))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        funderTokenAccount: funderRewardTokenAccount,
+        protocolVault: protocolRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        solVault: null,
+        authority: wallet.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.rewardBudget.toNumber(), budget);
+    assert.ok(pool.budgetEnforced);
+  });
+
+  it("Rejects deposits the budget cannot pay for", async () => {
+    // A year at 10% on 1_000_000 needs 100_000 in rewards
+    try {
+      await deposit(1_000_000);
+      assert.fail("The budget only covers half a year of this deposit");
+    } catch (error) {
+      assert.include(error.message, "PoolInsolvent");
+    }
+
+    await deposit(100_000);
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.totalDeposited.toNumber(), 100_000);
+  });
+
+  it("Reserves a full referral chain on top of the rewards", async () => {
+    // 450_000 needs 45_000 a year, plus up to 20% of that for referrers
+    try {
+      await deposit(350_000);
+      assert.fail("The budget can't cover the referral shares as well");
+    } catch (error) {
+      assert.include(error.message, "PoolInsolvent");
+    }
+
+    // 400_000 needs 40_000 plus 8_000 in referral shares
+    await deposit(300_000);
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.totalDeposited.toNumber(), 400_000);
+  });
+});
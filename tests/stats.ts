@@ -0,0 +1,233 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("pool and protocol statistics", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, balance, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  const amount = new anchor.BN(100_000);
+
+  let protocolStatsPDA: PublicKey;
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let rewardTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let protocolRewardTokenAccount: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+  let userRewardTokenAccount: PublicKey;
+
+  const poolStats = async () => (await program.account.pool.fetch(poolPDA)).stats;
+  const protocolStats = () => program.account.protocolStats.fetch(protocolStatsPDA);
+
+  before(async () => {
+    [protocolStatsPDA] = PublicKey.findProgramAddressSync([Buffer.from("protocol_stats")], program.programId);
+    depositTokenMint = await newMint();
+    rewardTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, rewardTokenMint, { canSwap: true }));
+    await airdrop(user.publicKey);
+
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    protocolRewardTokenAccount = await ata(rewardTokenMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    userRewardTokenAccount = await ata(rewardTokenMint, user.publicKey);
+
+    await mintTokens(depositTokenMint, userDepositTokenAccount, 1_000_000);
+    await mintTokens(rewardTokenMint, protocolRewardTokenAccount, 1_000_000);
+  });
+
+  it("Counts a new depositor and their open position", async () => {
+    const before = await protocolStats();
+
+    await program.methods
+      .deposit(poolId, amount, null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        protocolStats: protocolStatsPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+    const after = await protocolStats();
+    assert.equal(after.depositors.toNumber(), before.depositors.toNumber() + 1);
+    assert.equal(after.activePositions.toNumber(), before.activePositions.toNumber() + 1);
+    assert.equal((await poolStats()).activePositions.toNumber(), 1);
+    assert.equal((await poolStats()).depositors.toNumber(), 1);
+  });
+
+  it("Adds claimed rewards to the pool's total", async () => {
+    const reward = 5_000;
+    await program.methods
+      .testHelperSetPendingReward(new anchor.BN(reward))
+      .accounts({ userInfo: userInfo.publicKey, authority: wallet.publicKey, protocol: protocolPDA })
+      .rpc();
+
+    await program.methods
+      .claim(poolId)
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        protocolVault: protocolRewardTokenAccount,
+        referralConfig: null,
+        userTokenAccount: userRewardTokenAccount,
+        rewardMint: rewardTokenMint,
+        solVault: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.equal((await poolStats()).rewardsPaid.toNumber(), reward);
+  });
+
+  it("Tracks swap volume per direction", async () => {
+    // At a 1:1 rate each swap returns what went in
+    const swap = (amount: number, direction: boolean) =>
+      program.methods
+        .swap(poolId, new anchor.BN(amount), direction)
+        .accounts({
+          pool: poolPDA,
+          protocol: protocolPDA,
+          userInputAccount: direction ? userDepositTokenAccount : userRewardTokenAccount,
+          protocolInputAccount: direction ? protocolDepositTokenAccount : protocolRewardTokenAccount,
+          protocolOutputAccount: direction ? protocolRewardTokenAccount : protocolDepositTokenAccount,
+          userOutputAccount: direction ? userRewardTokenAccount : userDepositTokenAccount,
+          inputMint: direction ? depositTokenMint : rewardTokenMint,
+          outputMint: direction ? rewardTokenMint : depositTokenMint,
+          poolHistory: null,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    const rewardBefore = await balance(userRewardTokenAccount);
+    await swap(10_000, true);
+    assert.equal(await balance(userRewardTokenAccount) - rewardBefore, 10_000);
+
+    const depositBefore = await balance(userDepositTokenAccount);
+    await swap(4_000, false);
+    assert.equal(await balance(userDepositTokenAccount) - depositBefore, 4_000);
+
+    const stats = await poolStats();
+    assert.equal(stats.swapVolumeToReward.toNumber(), 10_000);
+    assert.equal(stats.swapVolumeToDeposit.toNumber(), 4_000);
+  });
+
+  it("Rejects a swap outside the pool's pair or into an account the protocol doesn't own", async () => {
+    const worthlessMint = await newMint();
+    const userWorthlessAccount = await ata(worthlessMint, user.publicKey);
+    const protocolWorthlessAccount = await ata(worthlessMint, protocolPDA);
+    await mintTokens(worthlessMint, userWorthlessAccount, 10_000);
+
+    const swap = (inputMint: PublicKey, userInputAccount: PublicKey, protocolInputAccount: PublicKey) =>
+      program.methods
+        .swap(poolId, new anchor.BN(10_000), true)
+        .accounts({
+          pool: poolPDA,
+          protocol: protocolPDA,
+          userInputAccount,
+          protocolInputAccount,
+          protocolOutputAccount: protocolRewardTokenAccount,
+          userOutputAccount: userRewardTokenAccount,
+          inputMint,
+          outputMint: rewardTokenMint,
+          poolHistory: null,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    try {
+      await swap(worthlessMint, userWorthlessAccount, protocolWorthlessAccount);
+      assert.fail("Only the pool's deposit token swaps into its reward token");
+    } catch (error) {
+      assert.include(error.message, "InvalidMint");
+    }
+
+    try {
+      await swap(depositTokenMint, userDepositTokenAccount, userDepositTokenAccount);
+      assert.fail("The input has to land in the protocol's account");
+    } catch (error) {
+      assert.include(error.message, "InvalidProtocolTokenAccount");
+    }
+  });
+
+  it("Closes the position on a full withdrawal", async () => {
+    const before = await protocolStats();
+
+    await program.methods
+      .withdraw(poolId)
+      .accounts({
+        protocol: protocolPDA,
+        protocolStats: protocolStatsPDA,
+        userInfo: userInfo.publicKey,
+        user: user.publicKey,
+        pool: poolPDA,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        userTokenAccount: userDepositTokenAccount,
+        solVault: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const after = await protocolStats();
+    assert.equal(after.activePositions.toNumber(), before.activePositions.toNumber() - 1);
+    assert.equal(after.depositors.toNumber(), before.depositors.toNumber());
+    assert.equal((await poolStats()).activePositions.toNumber(), 0);
+  });
+
+  it("Counts a wallet once however many positions and pools it deposits in", async () => {
+    const { poolId: otherPoolId, poolPDA: otherPoolPDA } = await addPool(depositTokenMint, rewardTokenMint);
+    const before = await protocolStats();
+
+    for (const [id, pool] of [[poolId, poolPDA], [otherPoolId, otherPoolPDA]] as [anchor.BN, PublicKey][]) {
+      const freshUserInfo = Keypair.generate();
+      await program.methods
+        .deposit(id, amount, null)
+        .accounts({
+          pool,
+          userInfo: freshUserInfo.publicKey,
+          protocol: protocolPDA,
+          protocolStats: protocolStatsPDA,
+          userTokenAccount: userDepositTokenAccount,
+          protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
+          solVault: null,
+          referralCode: null,
+          referrerAccount: null,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user, freshUserInfo])
+        .rpc();
+    }
+
+    assert.equal((await protocolStats()).depositors.toNumber(), before.depositors.toNumber());
+    assert.equal((await poolStats()).depositors.toNumber(), 1);
+    assert.equal((await program.account.pool.fetch(otherPoolPDA)).stats.depositors.toNumber(), 1);
+  });
+});
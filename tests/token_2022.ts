@@ -0,0 +1,110 @@
+import * as anchor from "@coral-xyz/anchor";
+import {
+  ExtensionType,
+  TOKEN_2022_PROGRAM_ID,
+  createInitializeMintInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  getMintLen,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, Transaction, TransactionInstruction } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("token-2022 pools", () => {
+  const {
+    provider, program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, balance, airdrop,
+  } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  const decimals = 6;
+  const feeBasisPoints = 100; // 1%
+
+  let rewardTokenMint: PublicKey;
+
+  // Creates a Token-2022 mint with the given extensions, initialized by `extensionInstructions`
+  const createMint2022 = async (
+    extensions: ExtensionType[],
+    extensionInstructions: (mint: PublicKey) => TransactionInstruction[]
+  ) => {
+    const mint = Keypair.generate();
+    const mintLen = getMintLen(extensions);
+    const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: wallet.publicKey,
+          newAccountPubkey: mint.publicKey,
+          space: mintLen,
+          lamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        ...extensionInstructions(mint.publicKey),
+        createInitializeMintInstruction(mint.publicKey, decimals, wallet.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      ),
+      [mint]
+    );
+    return mint.publicKey;
+  };
+
+  const add2022Pool = (depositToken: PublicKey) =>
+    addPool(depositToken, rewardTokenMint, { tokenProgram: TOKEN_2022_PROGRAM_ID });
+
+  before(async () => {
+    rewardTokenMint = await newMint(decimals);
+    await airdrop(user.publicKey);
+  });
+
+  it("Rejects mints with a permanent delegate", async () => {
+    const mint = await createMint2022([ExtensionType.PermanentDelegate], (mint) => [
+      createInitializePermanentDelegateInstruction(mint, wallet.publicKey, TOKEN_2022_PROGRAM_ID),
+    ]);
+    try {
+      await add2022Pool(mint);
+      assert.fail("A permanent delegate could move pool funds");
+    } catch (error) {
+      assert.include(error.message, "UnsupportedMintExtension");
+    }
+  });
+
+  it("Credits deposits with what arrives after the transfer fee", async () => {
+    const mint = await createMint2022([ExtensionType.TransferFeeConfig], (mint) => [
+      createInitializeTransferFeeConfigInstruction(
+        mint, wallet.publicKey, wallet.publicKey, feeBasisPoints, BigInt(1_000_000_000), TOKEN_2022_PROGRAM_ID
+      ),
+    ]);
+    const { poolId, poolPDA } = await add2022Pool(mint);
+
+    const userTokenAccount = await ata(mint, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    const protocolTokenAccount = await ata(mint, protocolPDA, TOKEN_2022_PROGRAM_ID);
+    await mintTokens(mint, userTokenAccount, 10_000_000, TOKEN_2022_PROGRAM_ID);
+
+    const amount = 1_000_000;
+    const fee = amount * feeBasisPoints / 10000;
+    await program.methods
+      .deposit(poolId, new anchor.BN(amount), null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount,
+        protocolTokenAccount,
+        depositMint: mint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+
+    assert.equal(await balance(protocolTokenAccount, TOKEN_2022_PROGRAM_ID), amount - fee);
+
+    const info = await program.account.userInfo.fetch(userInfo.publicKey);
+    assert.equal(info.amount.toNumber(), amount - fee, "Only what arrived should be credited");
+    assert.equal(info.deposits[0].amount.toNumber(), amount - fee);
+  });
+});
@@ -0,0 +1,204 @@
+import * as anchor from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+import { fixtures } from "./helpers/fixtures";
+
+describe("unbonding pools", () => {
+  const { program, wallet, protocolPDA, addPool, newMint, ata, mintTokens, balance, airdrop } = fixtures();
+
+  const user = Keypair.generate();
+  const userInfo = Keypair.generate();
+  const cooldown = 2;
+  const stake = 100_000;
+  const unstake = 40_000;
+
+  let poolPDA: PublicKey;
+  let poolId: anchor.BN;
+  let depositTokenMint: PublicKey;
+  let protocolDepositTokenAccount: PublicKey;
+  let userDepositTokenAccount: PublicKey;
+
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  const completeUnstake = (requestId: number) =>
+    program.methods
+      .completeUnstake(poolId, new anchor.BN(requestId))
+      .accounts({
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        user: user.publicKey,
+        pool: poolPDA,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        userTokenAccount: userDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+  before(async () => {
+    depositTokenMint = await newMint();
+    ({ poolId, poolPDA } = await addPool(depositTokenMint, await newMint()));
+
+    await program.methods
+      .setCooldown(poolId, new anchor.BN(cooldown))
+      .accounts({
+        pool: poolPDA,
+        protocol: protocolPDA,
+        authority: wallet.publicKey,
+        configRegistry: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await airdrop(user.publicKey);
+    protocolDepositTokenAccount = await ata(depositTokenMint, protocolPDA);
+    userDepositTokenAccount = await ata(depositTokenMint, user.publicKey);
+    await mintTokens(depositTokenMint, userDepositTokenAccount, stake);
+
+    await program.methods
+      .deposit(poolId, new anchor.BN(stake), null)
+      .accounts({
+        pool: poolPDA,
+        userInfo: userInfo.publicKey,
+        protocol: protocolPDA,
+        userTokenAccount: userDepositTokenAccount,
+        protocolTokenAccount: protocolDepositTokenAccount,
+        depositMint: depositTokenMint,
+        solVault: null,
+        referralCode: null,
+        referrerAccount: null,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, userInfo])
+      .rpc();
+  });
+
+  it("Requires an unstake request instead of a plain withdrawal", async () => {
+    try {
+      await program.methods
+        .withdraw(poolId)
+        .accounts({
+          protocol: protocolPDA,
+          userInfo: userInfo.publicKey,
+          user: user.publicKey,
+          pool: poolPDA,
+          protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
+          userTokenAccount: userDepositTokenAccount,
+          solVault: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Unbonding pools have no plain withdrawals");
+    } catch (error) {
+      assert.include(error.message, "UnbondingRequired");
+    }
+  });
+
+  it("Rejects an unstake against a pool the position is not in", async () => {
+    const [otherPoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), protocolPDA.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    try {
+      await program.methods
+        .requestUnstake(new anchor.BN(0), new anchor.BN(unstake))
+        .accounts({
+          protocol: protocolPDA,
+          userInfo: userInfo.publicKey,
+          pool: otherPoolPDA,
+          user: user.publicKey,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("The position was opened in another pool");
+    } catch (error) {
+      assert.include(error.message, "InvalidUserInfoPool");
+    }
+  });
+
+  it("Rejects a withdrawal against a pool the position is not in", async () => {
+    const [otherPoolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), protocolPDA.toBuffer(), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    try {
+      await program.methods
+        .withdraw(new anchor.BN(0))
+        .accounts({
+          protocol: protocolPDA,
+          userInfo: userInfo.publicKey,
+          user: user.publicKey,
+          pool: otherPoolPDA,
+          protocolTokenAccount: protocolDepositTokenAccount,
+          depositMint: depositTokenMint,
+          userTokenAccount: userDepositTokenAccount,
+          solVault: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("The position was opened in another pool");
+    } catch (error) {
+      assert.include(error.message, "InvalidUserInfoPool");
+    }
+  });
+
+  it("Stops the unbonding amount from earning", async () => {
+    await program.methods
+      .requestUnstake(poolId, new anchor.BN(unstake))
+      .accounts({
+        protocol: protocolPDA,
+        userInfo: userInfo.publicKey,
+        pool: poolPDA,
+        user: user.publicKey,
+      })
+      .signers([user])
+      .rpc();
+
+    const info = await program.account.userInfo.fetch(userInfo.publicKey);
+    assert.equal(info.amount.toNumber(), stake - unstake);
+    assert.equal(info.deposits[0].amount.toNumber(), stake - unstake);
+    assert.equal(info.unstakeRequests.length, 1);
+    assert.equal(info.unstakeRequests[0].amount.toNumber(), unstake);
+
+    const pool = await program.account.pool.fetch(poolPDA);
+    assert.equal(pool.totalDeposited.toNumber(), stake - unstake);
+    assert.equal(pool.totalUnbonding.toNumber(), unstake);
+  });
+
+  it("Pays out once the cooldown has passed", async () => {
+    try {
+      await completeUnstake(0);
+      assert.fail("The cooldown has not passed yet");
+    } catch (error) {
+      assert.include(error.message, "CooldownNotElapsed");
+    }
+
+    await sleep((cooldown + 1) * 1000);
+    await completeUnstake(0);
+
+    assert.equal(await balance(userDepositTokenAccount), unstake);
+    const info = await program.account.userInfo.fetch(userInfo.publicKey);
+    assert.equal(info.unstakeRequests.length, 0);
+    assert.equal((await program.account.pool.fetch(poolPDA)).totalUnbonding.toNumber(), 0);
+
+    try {
+      await completeUnstake(0);
+      assert.fail("A request is only paid once");
+    } catch (error) {
+      assert.include(error.message, "UnstakeRequestNotFound");
+    }
+  });
+});